@@ -0,0 +1,56 @@
+#!/bin/sh
+# Times renders of a scene at several thread counts, for comparing revisions of the
+# renderer on the same machine, e.g. the last commit against its parent:
+#
+#   ./bench.sh HEAD~1 HEAD
+#
+# Each revision is built in a temporary worktree; with no revisions the working tree is
+# timed. The time is the render time the renderer reports, best of RUNS runs. Set SCENE,
+# WIDTH, HEIGHT, SAMPLES, THREADS and RUNS to change the workload.
+set -e
+
+SCENE=${SCENE:-scenes/test.yml}
+WIDTH=${WIDTH:-640}
+HEIGHT=${HEIGHT:-360}
+SAMPLES=${SAMPLES:-16}
+THREADS=${THREADS:-"1 2 4 8 $(nproc)"}
+RUNS=${RUNS:-3}
+
+root=$(cd "$(dirname "$0")" && pwd)
+work=$(mktemp -d)
+trap 'git -C "$root" worktree prune; rm -rf "$work"' EXIT
+
+# Prints the best render time in seconds of $RUNS runs of the binary $1 with $2 threads,
+# run from the source tree $3 so the scene's relative paths resolve.
+best_time() {
+    best=
+    for _ in $(seq "$RUNS"); do
+        time=$(cd "$3" && "$1" "$work/out.png" --world "$SCENE" -w "$WIDTH" -h "$HEIGHT" \
+            -s "$SAMPLES" -t "$2" 2>&1 | sed -n 's/^Took \([0-9.]*\)s$/\1/p')
+        if [ -z "$best" ] || awk "BEGIN { exit !($time < $best) }"; then
+            best=$time
+        fi
+    done
+    echo "$best"
+}
+
+if [ $# -eq 0 ]; then
+    set -- working-tree
+fi
+echo "$SCENE at ${WIDTH}x$HEIGHT, $SAMPLES spp, best of $RUNS, nproc = $(nproc)"
+for revision in "$@"; do
+    if [ "$revision" = working-tree ]; then
+        source=$root
+    else
+        source=$work/$revision
+        git -C "$root" worktree add --detach "$source" "$revision" >/dev/null 2>&1
+        # Build every revision with the same dependency versions.
+        cp "$root/Cargo.lock" "$source/"
+    fi
+    cargo build --release --quiet --manifest-path "$source/Cargo.toml" \
+        --target-dir "$work/target-$revision"
+    binary=$work/target-$revision/release/raytracing-oneweekend
+    for threads in $(echo "$THREADS" | tr ' ' '\n' | sort -nu); do
+        echo "$revision  $threads threads  $(best_time "$binary" "$threads" "$source")s"
+    done
+done
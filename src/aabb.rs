@@ -0,0 +1,109 @@
+use crate::{
+    ray::Ray,
+    vectors::{Point3, Vec3},
+};
+/// Axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+impl Aabb {
+    pub fn new(min: Point3, max: Point3) -> Aabb {
+        Aabb { min, max }
+    }
+    /// A box containing nothing, which any `surrounding` call will replace.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Point3::new_all(f32::INFINITY),
+            max: Point3::new_all(f32::NEG_INFINITY),
+        }
+    }
+    pub fn surrounding(&self, other: Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+    pub fn including(&self, p: Point3) -> Aabb {
+        self.surrounding(Aabb::new(p, p))
+    }
+    pub fn centroid(&self) -> Point3 {
+        (self.min + self.max) * Vec3::new_all(0.5)
+    }
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+    pub fn surface_area(&self) -> f32 {
+        let d = self.extent();
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+    /// Index of the longest axis: 0 for x, 1 for y, 2 for z.
+    pub fn longest_axis(&self) -> usize {
+        let d = self.extent();
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+    /// Slab test. `inv_direction` is the componentwise reciprocal of the ray direction.
+    #[inline(always)]
+    pub fn hit(&self, r: Ray, inv_direction: Vec3, mut t_min: f32, mut t_max: f32) -> bool {
+        for axis in 0..3 {
+            let inv_d = inv_direction.axis(axis);
+            let mut t0 = (self.min.axis(axis) - r.origin.axis(axis)) * inv_d;
+            let mut t1 = (self.max.axis(axis) - r.origin.axis(axis)) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // Written so that NaNs from 0 * inf leave the interval untouched.
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aabb_hit() {
+        let aabb = Aabb::new(Point3::new_all(-1.0), Point3::new_all(1.0));
+        let r = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let inv = Vec3::new_all(1.0) / r.direction;
+        assert!(aabb.hit(r, inv, 0.001, f32::MAX));
+        assert!(!aabb.hit(r, inv, 0.001, 3.0));
+
+        let r = Ray::new(Point3::new(0.0, 2.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let inv = Vec3::new_all(1.0) / r.direction;
+        assert!(!aabb.hit(r, inv, 0.001, f32::MAX));
+    }
+
+    #[test]
+    fn test_aabb_surrounding() {
+        let a = Aabb::new(Point3::new_all(0.0), Point3::new_all(1.0));
+        let b = Aabb::new(Point3::new(-1.0, 0.5, 0.5), Point3::new(0.5, 2.0, 0.5));
+        let c = a.surrounding(b);
+        assert_eq!(c.min, Point3::new(-1.0, 0.0, 0.0));
+        assert_eq!(c.max, Point3::new(1.0, 2.0, 1.0));
+        assert_eq!(Aabb::empty().surrounding(a), a);
+    }
+}
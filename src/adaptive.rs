@@ -0,0 +1,127 @@
+use crate::{environment::luminance, image::Image, vectors::Color};
+
+/// Running mean and variance of the samples of one pixel, using Welford's algorithm on
+/// their luminance.
+#[derive(Debug, Clone, Copy)]
+pub struct PixelEstimate {
+    pub count: u32,
+    pub sum: Color,
+    /// Mean of the luminance
+    pub mean: f32,
+    /// Sum of the squared differences of the luminance from the mean
+    pub m2: f32,
+}
+impl PixelEstimate {
+    pub fn new() -> PixelEstimate {
+        PixelEstimate {
+            count: 0,
+            sum: Color::new_all(0.0),
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+    pub fn add(&mut self, sample: Color) {
+        self.count += 1;
+        self.sum = self.sum + sample;
+        let l = luminance(sample);
+        let delta = l - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (l - self.mean);
+    }
+    /// Standard error of the mean luminance relative to its square root, which roughly
+    /// follows how visible noise is at different brightnesses.
+    pub fn error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+        let variance = self.m2 / (self.count - 1) as f32;
+        (variance / self.count as f32).sqrt() / self.mean.max(1e-4).sqrt()
+    }
+}
+
+/// How many samples each pixel gets.
+#[derive(Debug, Clone, Copy)]
+pub struct SamplingPlan {
+    /// Samples taken before the error is first checked, and between later checks
+    pub min_samples: u32,
+    pub max_samples: u32,
+    /// Pixels stop being sampled once their `PixelEstimate::error` is below this. Without
+    /// it every pixel gets `max_samples`.
+    pub noise_threshold: Option<f32>,
+}
+impl SamplingPlan {
+    /// Number of samples to add to a pixel, or 0 once it is done.
+    pub fn next_batch(&self, estimate: &PixelEstimate) -> u32 {
+        let remaining = self.max_samples.saturating_sub(estimate.count);
+        match self.noise_threshold {
+            None => remaining,
+            Some(_) if estimate.count == 0 => self.min_samples.max(1).min(remaining),
+            Some(threshold) if estimate.error() < threshold => 0,
+            Some(_) => self.min_samples.max(1).min(remaining),
+        }
+    }
+}
+
+/// Colors each pixel by how many samples it got, from blue for none to red for `max`.
+pub fn heatmap(samples: &Image, max: u32) -> Image {
+    samples.map(|c| {
+        let t = (c.x / max.max(1) as f32).clamp(0.0, 1.0);
+        // Blue through green to red
+        Color::new(
+            (2.0 * t - 1.0).max(0.0),
+            1.0 - (2.0 * t - 1.0).abs(),
+            (1.0 - 2.0 * t).max(0.0),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_matches_direct_computation() {
+        let samples = [0.5f32, 1.5, 1.0, 3.0, 0.0, 2.0];
+        let mut estimate = PixelEstimate::new();
+        for &s in &samples {
+            estimate.add(Color::new_all(s));
+        }
+        let n = samples.len() as f32;
+        let mean = samples.iter().sum::<f32>() / n;
+        let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / (n - 1.0);
+        let expected = (variance / n).sqrt() / mean.sqrt();
+        assert!((estimate.error() - expected).abs() < 1e-5);
+        assert_eq!(estimate.sum, Color::new_all(8.0));
+    }
+
+    #[test]
+    fn test_plan_stops_converged_pixels() {
+        let plan = SamplingPlan {
+            min_samples: 4,
+            max_samples: 10,
+            noise_threshold: Some(0.01),
+        };
+        let mut estimate = PixelEstimate::new();
+        assert_eq!(plan.next_batch(&estimate), 4);
+        for _ in 0..4 {
+            estimate.add(Color::new_all(0.5));
+        }
+        // No variance at all, so the pixel is done.
+        assert_eq!(plan.next_batch(&estimate), 0);
+
+        let mut noisy = PixelEstimate::new();
+        for i in 0..8 {
+            noisy.add(Color::new_all((i % 2) as f32));
+        }
+        // Capped by the maximum.
+        assert_eq!(plan.next_batch(&noisy), 2);
+        assert_eq!(
+            SamplingPlan {
+                noise_threshold: None,
+                ..plan
+            }
+            .next_batch(&noisy),
+            2
+        );
+    }
+}
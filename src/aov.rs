@@ -0,0 +1,199 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::Camera, hittablelist::HittableList, image::Image, ray::Ray, tonemap::srgb_oetf,
+    vectors::Color,
+};
+
+/// Arbitrary output variable: a property of the first surface seen through each pixel,
+/// written alongside the rendered image. Pixels where the camera ray hits nothing are 0.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Aov {
+    /// Distance from the camera along its line of sight
+    Depth,
+    /// World-space shading normal, facing the camera
+    Normal,
+    /// Base color of the material
+    Albedo,
+    /// One plus the index of the object in the scene's object list
+    ObjectId,
+    /// Number of the material in the order the scene lists them, counting the materials of
+    /// each object separately
+    MaterialId,
+    /// World-space hit point
+    Position,
+}
+impl FromStr for Aov {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "depth" => Ok(Aov::Depth),
+            "normal" => Ok(Aov::Normal),
+            "albedo" => Ok(Aov::Albedo),
+            "object_id" => Ok(Aov::ObjectId),
+            "material_id" => Ok(Aov::MaterialId),
+            "position" => Ok(Aov::Position),
+            _ => Err(anyhow!(
+                "unknown AOV {}, expected depth, normal, albedo, object_id, material_id or position",
+                s
+            )),
+        }
+    }
+}
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Position => "position",
+        }
+    }
+    /// Names of the EXR channels holding the components of the values.
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+        }
+    }
+    /// Whether samples are averaged over the pixel. Ids would turn into meaningless
+    /// blends, so they come from the first sample instead.
+    pub fn is_averaged(&self) -> bool {
+        !matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+    /// Evaluates the requested `aovs` for the camera ray `r`. Those that are not averaged
+    /// are only evaluated for the `first_sample` of a pixel, and are 0 for the others.
+    pub fn evaluate(
+        aovs: &[Aov],
+        first_sample: bool,
+        r: Ray,
+        world: &HittableList,
+        camera: &Camera,
+    ) -> Vec<Color> {
+        if !first_sample && !aovs.iter().any(Aov::is_averaged) {
+            return vec![Color::new_all(0.0); aovs.len()];
+        }
+        let (object, rec) = match world.hit_object(r, 0.001, f32::MAX) {
+            Some(hit) => hit,
+            None => return vec![Color::new_all(0.0); aovs.len()],
+        };
+        aovs.iter()
+            .map(|aov| match aov {
+                _ if !first_sample && !aov.is_averaged() => Color::new_all(0.0),
+                Aov::Depth => Color::new_all((rec.p - camera.origin()).dot(camera.forward())),
+                Aov::Normal => rec.normal,
+                Aov::Albedo => rec.mat.albedo(&rec),
+                Aov::ObjectId => Color::new_all((object + 1) as f32),
+                Aov::MaterialId => Color::new_all(rec.material_id as f32),
+                Aov::Position => rec.p,
+            })
+            .collect()
+    }
+    /// Maps the values to colors for an 8-bit image: normals from [-1, 1] to [0, 1], depth
+    /// relative to the largest in the image, sRGB-encoded albedo, and random colors for ids.
+    pub fn for_display(&self, image: &Image) -> Image {
+        match self {
+            Aov::Depth => {
+                let max = image.pixels().iter().fold(0.0f32, |m, c| m.max(c.x));
+                image.map(|c| {
+                    if max > 0.0 {
+                        c / Color::new_all(max)
+                    } else {
+                        c
+                    }
+                })
+            }
+            Aov::Normal => image.map(|c| (c + Color::new_all(1.0)) * Color::new_all(0.5)),
+            Aov::Albedo => {
+                image.map(|c| Color::new(srgb_oetf(c.x), srgb_oetf(c.y), srgb_oetf(c.z)))
+            }
+            Aov::ObjectId | Aov::MaterialId => image.map(|c| {
+                if c.x == 0.0 {
+                    return c;
+                }
+                let mut hasher = DefaultHasher::new();
+                (c.x as u32).hash(&mut hasher);
+                let h = hasher.finish();
+                let channel = |shift: u32| ((h >> shift) & 0xff) as f32 / 255.0;
+                Color::new(channel(0), channel(8), channel(16))
+            }),
+            Aov::Position => image.map(|c| c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::Diffuse,
+        shapes::sphere::Sphere,
+        vectors::{Point3, Vec3},
+    };
+
+    #[test]
+    fn test_evaluate_aovs() {
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, 0.0, -5.0),
+            1.0,
+            Box::new(Diffuse::new(Color::new(0.2, 0.4, 0.6))),
+        )));
+        let camera = Camera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            0.0,
+            1.0,
+        );
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, 0.0, 5.0),
+            1.0,
+            Box::new(Diffuse::new(Color::new(0.2, 0.4, 0.6))),
+        )));
+        let aovs = [
+            Aov::Depth,
+            Aov::Normal,
+            Aov::Albedo,
+            Aov::ObjectId,
+            Aov::Position,
+            Aov::MaterialId,
+        ];
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let values = Aov::evaluate(&aovs, true, r, &world, &camera);
+        assert_eq!(values[0], Color::new_all(4.0));
+        assert_eq!(values[1], Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(values[2], Color::new(0.2, 0.4, 0.6));
+        assert_eq!(values[3], Color::new_all(1.0));
+        assert_eq!(values[4], Point3::new(0.0, 0.0, -4.0));
+        assert_eq!(values[5], Color::new_all(1.0));
+        // Ids are only evaluated for the first sample of a pixel.
+        let values = Aov::evaluate(&aovs, false, r, &world, &camera);
+        assert_eq!(values[0], Color::new_all(4.0));
+        assert_eq!(values[3], Color::new_all(0.0));
+        // Equal materials on different objects still get their own ids.
+        let behind = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let values = Aov::evaluate(&aovs, true, behind, &world, &camera);
+        assert_eq!(values[3], Color::new_all(2.0));
+        assert_eq!(values[5], Color::new_all(2.0));
+
+        let miss = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(Aov::evaluate(&aovs, true, miss, &world, &camera)
+            .iter()
+            .all(|c| *c == Color::new_all(0.0)));
+    }
+}
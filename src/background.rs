@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    environment::EnvironmentMap,
+    ray::Ray,
+    vectors::{Color, Vec3},
+};
+/// What a ray sees when it escapes the scene.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Background {
+    /// The same color in every direction.
+    Solid { color: Color },
+    /// A vertical blend from `bottom` (looking straight down) to `top` (looking straight up).
+    Gradient { bottom: Color, top: Color },
+    /// No light at all, for scenes lit only by emissive objects.
+    Black,
+    /// An HDR latitude-longitude image, importance sampled by luminance.
+    Environment(EnvironmentMap),
+}
+impl Default for Background {
+    fn default() -> Self {
+        Background::Gradient {
+            bottom: Color::new(1.0, 1.0, 1.0),
+            top: Color::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+impl Background {
+    pub fn value(&self, r: Ray) -> Color {
+        match self {
+            Background::Solid { color } => *color,
+            Background::Gradient { bottom, top } => {
+                let unit_direction = r.direction.normalize();
+                let t = 0.5 * (unit_direction.y + 1.0);
+                Vec3::new_all(1.0 - t) * *bottom + Vec3::new_all(t) * *top
+            }
+            Background::Black => Color::new_all(0.0),
+            Background::Environment(env) => env.value(r.direction),
+        }
+    }
+    /// Samples a direction towards the background for direct lighting from two uniform
+    /// numbers. Returns the direction, the radiance arriving from it and its solid angle pdf,
+    /// or `None` if this background is not worth sampling explicitly.
+    pub fn sample(&self, u1: f32, u2: f32) -> Option<(Vec3, Color, f32)> {
+        match self {
+            Background::Environment(env) => env.sample(u1, u2),
+            _ => None,
+        }
+    }
+    /// Whether `sample` can return directions, so that direct lighting accounts for the
+    /// background.
+    pub fn is_sampled(&self) -> bool {
+        matches!(self, Background::Environment(_))
+    }
+    /// Solid angle pdf of `sample` returning `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        match self {
+            Background::Environment(env) => env.pdf(direction),
+            _ => 0.0,
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use crate::{
+    aabb::Aabb,
+    hittable::HitRecord,
+    ray::Ray,
+    vectors::{Point3, Vec3},
+};
+
+/// How the primitives of a BVH node are divided between its two children.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitMethod {
+    /// Binned surface area heuristic
+    Sah,
+    /// Halfway along the longest axis of the centroid bounds
+    Midpoint,
+}
+impl FromStr for SplitMethod {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sah" => Ok(SplitMethod::Sah),
+            "midpoint" => Ok(SplitMethod::Midpoint),
+            _ => Err(anyhow!(
+                "unknown BVH split method {}, expected sah or midpoint",
+                s
+            )),
+        }
+    }
+}
+
+const SAH_BUCKETS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+/// Past this depth nodes are split into equal halves, which bounds the traversal stack.
+const MAX_UNBALANCED_DEPTH: usize = 96;
+const STACK_SIZE: usize = 128;
+
+#[derive(Debug, Clone, Copy)]
+enum BvhNodeKind {
+    /// `count` primitives starting at `first` in `Bvh::indices`
+    Leaf { first: usize, count: usize },
+    /// The first child directly follows its parent
+    Interior { second_child: usize, axis: usize },
+}
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    bbox: Aabb,
+    kind: BvhNodeKind,
+}
+
+/// Bounding volume hierarchy over a list of primitives, stored as a flat array of nodes
+/// in depth-first order. The BVH only stores indices; the primitives themselves are
+/// intersected by the closure passed to `hit`.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+struct BuildPrimitive {
+    index: usize,
+    bbox: Aabb,
+    centroid: Point3,
+}
+impl Bvh {
+    pub fn new(boxes: &[Aabb], split: SplitMethod) -> Bvh {
+        let mut primitives = boxes
+            .iter()
+            .enumerate()
+            .map(|(index, bbox)| BuildPrimitive {
+                index,
+                bbox: *bbox,
+                centroid: bbox.centroid(),
+            })
+            .collect::<Vec<_>>();
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * boxes.len()),
+            indices: Vec::with_capacity(boxes.len()),
+        };
+        if !primitives.is_empty() {
+            bvh.build(&mut primitives, split, 0);
+        }
+        bvh
+    }
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.nodes.first().map(|n| n.bbox)
+    }
+    /// Replaces every primitive index `i` with `mapping[i]`.
+    pub fn remap(mut self, mapping: &[usize]) -> Bvh {
+        for index in self.indices.iter_mut() {
+            *index = mapping[*index];
+        }
+        self
+    }
+
+    fn build(
+        &mut self,
+        primitives: &mut [BuildPrimitive],
+        split: SplitMethod,
+        depth: usize,
+    ) -> usize {
+        let bbox = primitives
+            .iter()
+            .fold(Aabb::empty(), |b, p| b.surrounding(p.bbox));
+        let node_index = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bbox,
+            kind: BvhNodeKind::Leaf { first: 0, count: 0 },
+        });
+
+        let centroid_bounds = primitives
+            .iter()
+            .fold(Aabb::empty(), |b, p| b.including(p.centroid));
+        let axis = centroid_bounds.longest_axis();
+        let mid = if primitives.len() == 1
+            || centroid_bounds.max.axis(axis) == centroid_bounds.min.axis(axis)
+        {
+            None
+        } else if depth >= MAX_UNBALANCED_DEPTH {
+            Some(split_equal(primitives, axis))
+        } else {
+            match split {
+                SplitMethod::Sah => Bvh::partition_sah(primitives, bbox, centroid_bounds, axis),
+                SplitMethod::Midpoint => Bvh::partition_midpoint(primitives, centroid_bounds, axis),
+            }
+        };
+
+        match mid {
+            Some(mid) => {
+                let (left, right) = primitives.split_at_mut(mid);
+                self.build(left, split, depth + 1);
+                let second_child = self.build(right, split, depth + 1);
+                self.nodes[node_index].kind = BvhNodeKind::Interior { second_child, axis };
+            }
+            None => {
+                let first = self.indices.len();
+                self.indices.extend(primitives.iter().map(|p| p.index));
+                self.nodes[node_index].kind = BvhNodeKind::Leaf {
+                    first,
+                    count: primitives.len(),
+                };
+            }
+        }
+        node_index
+    }
+
+    /// Returns where to split `primitives`, or `None` if they should stay in one leaf.
+    fn partition_sah(
+        primitives: &mut [BuildPrimitive],
+        bbox: Aabb,
+        centroid_bounds: Aabb,
+        axis: usize,
+    ) -> Option<usize> {
+        if primitives.len() <= 2 {
+            return Bvh::partition_midpoint(primitives, centroid_bounds, axis);
+        }
+        let min = centroid_bounds.min.axis(axis);
+        let extent = centroid_bounds.max.axis(axis) - min;
+        let bucket_of = |p: &BuildPrimitive| {
+            (((p.centroid.axis(axis) - min) / extent * SAH_BUCKETS as f32) as usize)
+                .min(SAH_BUCKETS - 1)
+        };
+
+        let mut counts = [0usize; SAH_BUCKETS];
+        let mut bounds = [Aabb::empty(); SAH_BUCKETS];
+        for p in primitives.iter() {
+            let b = bucket_of(p);
+            counts[b] += 1;
+            bounds[b] = bounds[b].surrounding(p.bbox);
+        }
+
+        // Cost of splitting after each bucket, relative to intersecting one primitive.
+        let (best_bucket, best_cost) = (0..SAH_BUCKETS - 1)
+            .map(|i| {
+                let (left, right) = (&counts[..=i], &counts[i + 1..]);
+                let left_box = bounds[..=i]
+                    .iter()
+                    .fold(Aabb::empty(), |a, b| a.surrounding(*b));
+                let right_box = bounds[i + 1..]
+                    .iter()
+                    .fold(Aabb::empty(), |a, b| a.surrounding(*b));
+                let cost = 0.125
+                    + (left.iter().sum::<usize>() as f32 * left_box.surface_area()
+                        + right.iter().sum::<usize>() as f32 * right_box.surface_area())
+                        / bbox.surface_area();
+                (i, cost)
+            })
+            .fold((0, f32::INFINITY), |best, candidate| {
+                if candidate.1 < best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            });
+
+        if primitives.len() <= MAX_LEAF_SIZE && best_cost >= primitives.len() as f32 {
+            return None;
+        }
+        let mid = partition(primitives, |p| bucket_of(p) <= best_bucket);
+        if mid == 0 || mid == primitives.len() {
+            return Bvh::partition_midpoint(primitives, centroid_bounds, axis);
+        }
+        Some(mid)
+    }
+
+    fn partition_midpoint(
+        primitives: &mut [BuildPrimitive],
+        centroid_bounds: Aabb,
+        axis: usize,
+    ) -> Option<usize> {
+        let pmid = centroid_bounds.centroid().axis(axis);
+        let mid = partition(primitives, |p| p.centroid.axis(axis) < pmid);
+        if mid == 0 || mid == primitives.len() {
+            // Everything landed on one side, so fall back to splitting into equal halves.
+            return Some(split_equal(primitives, axis));
+        }
+        Some(mid)
+    }
+
+    /// Finds the closest hit along `r`. `hit_primitive` is called with the index of a
+    /// primitive whose bounds the ray crosses, and the current `t_min` and `t_max`.
+    pub fn hit<F>(&self, r: Ray, t_min: f32, t_max: f32, mut hit_primitive: F) -> Option<HitRecord>
+    where
+        F: FnMut(usize, f32, f32) -> Option<HitRecord>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inv_direction = Vec3::new_all(1.0) / r.direction;
+        let direction_negative = [
+            inv_direction.x < 0.0,
+            inv_direction.y < 0.0,
+            inv_direction.z < 0.0,
+        ];
+
+        let mut closest_so_far = t_max;
+        let mut result = None;
+        let mut stack = [0usize; STACK_SIZE];
+        let mut stack_size = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node.bbox.hit(r, inv_direction, t_min, closest_so_far) {
+                match node.kind {
+                    BvhNodeKind::Leaf { first, count } => {
+                        for &index in &self.indices[first..first + count] {
+                            if let Some(rec) = hit_primitive(index, t_min, closest_so_far) {
+                                closest_so_far = rec.t;
+                                result = Some(rec);
+                            }
+                        }
+                    }
+                    BvhNodeKind::Interior { second_child, axis } => {
+                        // Visit the child nearer to the ray origin first.
+                        if direction_negative[axis] {
+                            stack[stack_size] = current + 1;
+                            current = second_child;
+                        } else {
+                            stack[stack_size] = second_child;
+                            current += 1;
+                        }
+                        stack_size += 1;
+                        continue;
+                    }
+                }
+            }
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            current = stack[stack_size];
+        }
+        result
+    }
+}
+
+/// Sorts `primitives` along `axis` and returns the index halfway through.
+fn split_equal(primitives: &mut [BuildPrimitive], axis: usize) -> usize {
+    // total_cmp, since degenerate primitives can have NaN centroids.
+    primitives.sort_by(|a, b| a.centroid.axis(axis).total_cmp(&b.centroid.axis(axis)));
+    primitives.len() / 2
+}
+
+/// Moves the elements matching `pred` to the front and returns how many there are.
+fn partition<T>(items: &mut [T], pred: impl Fn(&T) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
@@ -1,58 +1,283 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
     ray::Ray,
+    sampler::Sampler,
     vectors::{Point3, Vec3},
 };
+/// How image coordinates map to ray directions.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Projection {
+    /// Pinhole or thin-lens perspective, using the camera fov vertically
+    #[default]
+    Perspective,
+    /// Parallel rays; `height` is the height of the view in world units
+    Orthographic { height: f32 },
+    /// Equidistant fisheye; `fov` is the angle in degrees across the image circle, which
+    /// touches the top and bottom of the image
+    Fisheye { fov: f32 },
+    /// Full 360° by 180° latitude-longitude panorama
+    Equirectangular,
+    /// 360° around the vertical axis, with the camera fov as a perspective vertical extent
+    Cylindrical,
+}
 #[derive(Clone, Copy)]
 pub struct Camera {
     origin: Point3,
     lower_left_corner: Point3,
     horizontal: Vec3,
     vertical: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    lens_radius: f32,
+    aspect_ratio: f32,
+    /// Height of the perspective viewport one unit in front of the camera
+    viewport_height: f32,
+    projection: Projection,
 }
 fn deg2rad(deg: f32) -> f32 {
     deg * std::f32::consts::PI / 180.0
 }
 impl Camera {
+    /// `aperture` is the diameter of the lens; 0 gives a pinhole camera with everything
+    /// in focus. Objects `focus_dist` away from `lookfrom` are in perfect focus.
     pub fn new(
         lookfrom: Point3,
         lookat: Point3,
         vup: Vec3,
         vfov: f32,
         aspect_ratio: f32,
+        aperture: f32,
+        focus_dist: f32,
     ) -> Camera {
-        let mut cam = Camera {
-            origin: Point3::new_all(0.0),
-            lower_left_corner: Point3::new_all(0.0),
-            horizontal: Vec3::new_all(0.0),
-            vertical: Vec3::new_all(0.0),
-        };
         let theta = deg2rad(vfov);
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
-        let _focal_length = 1.0;
 
         let w = (lookfrom - lookat).normalize();
         let u = vup.cross(w).normalize();
         let v = w.cross(u);
 
-        cam.origin = lookfrom;
-        cam.horizontal = Vec3::new_all(viewport_width) * u;
-        cam.vertical = Vec3::new_all(viewport_height) * v;
-        cam.lower_left_corner = cam.origin
-            - cam.horizontal / Vec3::new_all(2.0)
-            - cam.vertical / Vec3::new_all(2.0)
-            - w;
-
-        cam
-    }
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        return Ray::new(
-            self.origin,
-            self.lower_left_corner
-                + Vec3::new_all(s) * self.horizontal
-                + Vec3::new_all(t) * self.vertical
-                - self.origin,
-        );
+        // The viewport lies on the plane of focus.
+        let origin = lookfrom;
+        let horizontal = Vec3::new_all(focus_dist * viewport_width) * u;
+        let vertical = Vec3::new_all(focus_dist * viewport_height) * v;
+        let lower_left_corner = origin
+            - horizontal / Vec3::new_all(2.0)
+            - vertical / Vec3::new_all(2.0)
+            - Vec3::new_all(focus_dist) * w;
+
+        Camera {
+            origin,
+            lower_left_corner,
+            horizontal,
+            vertical,
+            u,
+            v,
+            w,
+            lens_radius: aperture / 2.0,
+            aspect_ratio,
+            viewport_height,
+            projection: Projection::Perspective,
+        }
+    }
+    pub fn origin(&self) -> Point3 {
+        self.origin
+    }
+    /// Unit vector along the camera's line of sight
+    pub fn forward(&self) -> Vec3 {
+        -self.w
+    }
+    /// Switches to another projection. The aperture only affects perspective projections.
+    pub fn with_projection(mut self, projection: Projection) -> Camera {
+        self.projection = projection;
+        self
+    }
+    /// Ray through the image at (`s`, `t`), both in [0, 1] from the bottom left. `None`
+    /// where the projection does not cover the image, such as outside a fisheye circle.
+    pub fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Option<Ray> {
+        use std::f32::consts::PI;
+        let scale = |a: Vec3, k: f32| a * Vec3::new_all(k);
+        match self.projection {
+            Projection::Perspective => {
+                let (u1, u2) = sampler.get_2d();
+                let rd = Vec3::new_all(self.lens_radius) * Vec3::in_unit_disk_from(u1, u2);
+                let offset = self.u * Vec3::new_all(rd.x) + self.v * Vec3::new_all(rd.y);
+                Some(Ray::new(
+                    self.origin + offset,
+                    self.lower_left_corner
+                        + Vec3::new_all(s) * self.horizontal
+                        + Vec3::new_all(t) * self.vertical
+                        - self.origin
+                        - offset,
+                ))
+            }
+            Projection::Orthographic { height } => {
+                let width = height * self.aspect_ratio;
+                Some(Ray::new(
+                    self.origin
+                        + scale(self.u, (s - 0.5) * width)
+                        + scale(self.v, (t - 0.5) * height),
+                    -self.w,
+                ))
+            }
+            Projection::Fisheye { fov } => {
+                // Position on the image relative to the center, with the circle's radius as 1.
+                let x = (2.0 * s - 1.0) * self.aspect_ratio;
+                let y = 2.0 * t - 1.0;
+                let r = (x * x + y * y).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let theta = r * deg2rad(fov) / 2.0;
+                let phi = y.atan2(x);
+                Some(Ray::new(
+                    self.origin,
+                    scale(self.u, theta.sin() * phi.cos()) + scale(self.v, theta.sin() * phi.sin())
+                        - scale(self.w, theta.cos()),
+                ))
+            }
+            Projection::Equirectangular => {
+                let phi = (s - 0.5) * 2.0 * PI;
+                let elevation = (t - 0.5) * PI;
+                Some(Ray::new(
+                    self.origin,
+                    scale(
+                        scale(self.u, phi.sin()) - scale(self.w, phi.cos()),
+                        elevation.cos(),
+                    ) + scale(self.v, elevation.sin()),
+                ))
+            }
+            Projection::Cylindrical => {
+                let phi = (s - 0.5) * 2.0 * PI;
+                let y = (t - 0.5) * self.viewport_height;
+                Some(Ray::new(
+                    self.origin,
+                    scale(self.u, phi.sin()) - scale(self.w, phi.cos()) + scale(self.v, y),
+                ))
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use float_cmp::approx_eq;
+
+    use super::*;
+    use crate::sampler::IndependentSampler;
+
+    fn camera(projection: Projection) -> Camera {
+        Camera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            2.0,
+            0.0,
+            1.0,
+        )
+        .with_projection(projection)
+    }
+
+    #[test]
+    fn test_projections_look_forward_at_center() {
+        for projection in &[
+            Projection::Perspective,
+            Projection::Orthographic { height: 2.0 },
+            Projection::Fisheye { fov: 180.0 },
+            Projection::Equirectangular,
+            Projection::Cylindrical,
+        ] {
+            let r = camera(*projection)
+                .get_ray(0.5, 0.5, &mut IndependentSampler::new(0))
+                .unwrap();
+            let d = r.direction.normalize();
+            assert!(
+                approx_eq!(f32, d.z, -1.0, epsilon = 1e-6),
+                "{:?}",
+                projection
+            );
+        }
+    }
+
+    #[test]
+    fn test_equirectangular_covers_sphere() {
+        let cam = camera(Projection::Equirectangular);
+        let behind = cam
+            .get_ray(0.0, 0.5, &mut IndependentSampler::new(0))
+            .unwrap()
+            .direction;
+        assert!(approx_eq!(f32, behind.z, 1.0, epsilon = 1e-6));
+        let right = cam
+            .get_ray(0.75, 0.5, &mut IndependentSampler::new(0))
+            .unwrap()
+            .direction;
+        assert!(approx_eq!(f32, right.x, 1.0, epsilon = 1e-6));
+        let up = cam
+            .get_ray(0.5, 1.0, &mut IndependentSampler::new(0))
+            .unwrap()
+            .direction;
+        assert!(approx_eq!(f32, up.y, 1.0, epsilon = 1e-6));
+    }
+
+    #[test]
+    fn test_fisheye_circle() {
+        let cam = camera(Projection::Fisheye { fov: 180.0 });
+        // The top of the image circle looks straight up.
+        let up = cam
+            .get_ray(0.5, 1.0, &mut IndependentSampler::new(0))
+            .unwrap()
+            .direction;
+        assert!(approx_eq!(f32, up.y, 1.0, epsilon = 1e-6));
+        // Image corners lie outside the circle.
+        assert!(cam
+            .get_ray(0.0, 0.0, &mut IndependentSampler::new(0))
+            .is_none());
+    }
+
+    #[test]
+    fn test_thin_lens() {
+        let lens = |aperture| {
+            Camera::new(
+                Point3::new(1.0, 2.0, 3.0),
+                Point3::new(1.0, 2.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                60.0,
+                1.5,
+                aperture,
+                4.0,
+            )
+        };
+        let mut sampler = IndependentSampler::new(0);
+        // A pinhole camera starts every ray at its origin.
+        let pinhole = lens(0.0);
+        for i in 0..100 {
+            sampler.start_pixel_sample(0, 0, i);
+            let r = pinhole.get_ray(0.3, 0.7, &mut sampler).unwrap();
+            assert_eq!(r.origin, pinhole.origin());
+        }
+        // With a lens, rays through the same point of the image start all over the lens and
+        // meet again on the plane in focus, 4 units ahead.
+        let thin_lens = lens(0.5);
+        let focus = |r: Ray| {
+            let t = 4.0 / r.direction.dot(thin_lens.forward());
+            r.origin + r.direction * Vec3::new_all(t)
+        };
+        sampler.start_pixel_sample(0, 0, 0);
+        let expected = focus(thin_lens.get_ray(0.3, 0.7, &mut sampler).unwrap());
+        let mut spread = 0.0f32;
+        for i in 1..100 {
+            sampler.start_pixel_sample(0, 0, i);
+            let r = thin_lens.get_ray(0.3, 0.7, &mut sampler).unwrap();
+            let offset = r.origin - thin_lens.origin();
+            assert!(offset.length() <= 0.25 + 1e-6);
+            assert!(offset.dot(thin_lens.forward()).abs() < 1e-6);
+            spread = spread.max(offset.length());
+            assert!((focus(r) - expected).length() < 1e-4);
+        }
+        assert!(spread > 0.1);
     }
 }
@@ -0,0 +1,231 @@
+use std::{
+    fs::{rename, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    adaptive::PixelEstimate, aov::Aov, image::Image, sampler::SamplerKind, vectors::Color,
+};
+
+const MAGIC: &[u8; 8] = b"RTCHECK2";
+
+/// Everything needed to continue a render: the accumulated samples of every pixel and the
+/// settings the samples were drawn with. Samplers only depend on the seed, the pixel and the
+/// sample index, so the sample counts are all the random number state there is.
+pub struct RenderState {
+    pub header: Header,
+    /// Per pixel, row by row from the bottom left like the camera
+    pub pixels: Vec<PixelState>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    /// Samples per pixel the sampler is created for. The stratified sampler lays out its
+    /// strata for this count, so it stays the same when --samples is raised on resume.
+    pub sampler_samples: u32,
+    /// `fingerprint` of the scene file
+    pub scene: u64,
+    /// `fingerprint` of the integrator and the settings that decide which samples are taken
+    pub settings: u64,
+    pub aovs: Vec<Aov>,
+    /// Number of passes rendered so far
+    pub passes: u32,
+}
+#[derive(Debug, Clone)]
+pub struct PixelState {
+    pub estimate: PixelEstimate,
+    /// Sums of the averaged AOVs, and the first sample's value of the others
+    pub aovs: Vec<Color>,
+}
+
+impl RenderState {
+    pub fn new(header: Header) -> RenderState {
+        let pixel = PixelState {
+            estimate: PixelEstimate::new(),
+            aovs: vec![Color::new_all(0.0); header.aovs.len()],
+        };
+        let pixels = vec![pixel; header.width as usize * header.height as usize];
+        RenderState { header, pixels }
+    }
+    pub fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.header.width + x) as usize
+    }
+    /// Fails if the checkpoint cannot be continued as the render described by `header`: it
+    /// must have the same size, scene and settings, and all of the AOVs.
+    pub fn check_compatible(&self, header: &Header) -> Result<()> {
+        let saved = &self.header;
+        if (saved.width, saved.height) != (header.width, header.height) {
+            bail!(
+                "checkpoint is {}x{}, but the image is {}x{}",
+                saved.width,
+                saved.height,
+                header.width,
+                header.height
+            );
+        }
+        if saved.scene != header.scene {
+            bail!("checkpoint was rendered from a different scene file");
+        }
+        if saved.settings != header.settings {
+            bail!(
+                "checkpoint was rendered with a different integrator, --max-depth, --rr-depth, \
+                 --pass-samples or --min-samples"
+            );
+        }
+        if let Some(aov) = header.aovs.iter().find(|aov| !saved.aovs.contains(aov)) {
+            bail!("checkpoint has no {} AOV", aov.name());
+        }
+        Ok(())
+    }
+
+    /// Average of the samples of each pixel.
+    pub fn image(&self) -> Image {
+        self.to_image(|p| (p.estimate.sum, p.estimate.count))
+    }
+    /// Average of the `i`th AOV, or its first sample's value if it is not averaged.
+    pub fn aov_image(&self, i: usize) -> Image {
+        let averaged = self.header.aovs[i].is_averaged();
+        self.to_image(|p| (p.aovs[i], if averaged { p.estimate.count } else { 1 }))
+    }
+    pub fn sample_counts(&self) -> Image {
+        self.to_image(|p| (Color::new_all(p.estimate.count as f32), 1))
+    }
+    fn to_image(&self, f: impl Fn(&PixelState) -> (Color, u32)) -> Image {
+        let mut image = Image::new(self.header.width, self.header.height);
+        for y in 0..self.header.height {
+            for x in 0..self.header.width {
+                let (sum, count) = f(&self.pixels[self.index(x, y)]);
+                image.set_pixel(x, y, sum, count.max(1));
+            }
+        }
+        image
+    }
+
+    /// Writes the state to `path`. The file is written next to it first and then moved into
+    /// place, so an interrupted save leaves the previous checkpoint intact.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temporary = path.with_extension("tmp");
+        {
+            let file = File::create(&temporary)
+                .with_context(|| format!("creating {}", temporary.display()))?;
+            let mut w = BufWriter::new(file);
+            let header = serde_yaml::to_string(&self.header)?;
+            w.write_all(MAGIC)?;
+            w.write_all(&(header.len() as u64).to_le_bytes())?;
+            w.write_all(header.as_bytes())?;
+            let write_f32 = |w: &mut BufWriter<File>, v: f32| w.write_all(&v.to_le_bytes());
+            for p in &self.pixels {
+                w.write_all(&p.estimate.count.to_le_bytes())?;
+                for c in std::iter::once(&p.estimate.sum).chain(&p.aovs) {
+                    write_f32(&mut w, c.x)?;
+                    write_f32(&mut w, c.y)?;
+                    write_f32(&mut w, c.z)?;
+                }
+                write_f32(&mut w, p.estimate.mean)?;
+                write_f32(&mut w, p.estimate.m2)?;
+            }
+            w.flush()?;
+        }
+        rename(&temporary, path).with_context(|| format!("writing {}", path.display()))?;
+        Ok(())
+    }
+    pub fn load(path: &Path) -> Result<RenderState> {
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        let mut r = BufReader::new(file);
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("{} is not a checkpoint", path.display());
+        }
+        let mut bytes = [0; 8];
+        r.read_exact(&mut bytes)?;
+        let mut header = vec![0; u64::from_le_bytes(bytes) as usize];
+        r.read_exact(&mut header)?;
+        let header: Header = serde_yaml::from_slice(&header)?;
+
+        let mut state = RenderState::new(header);
+        let read_u32 = |r: &mut BufReader<File>| -> Result<u32> {
+            let mut bytes = [0; 4];
+            r.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        };
+        let read_f32 = |r: &mut BufReader<File>| read_u32(r).map(f32::from_bits);
+        for p in &mut state.pixels {
+            p.estimate.count = read_u32(&mut r)?;
+            for c in std::iter::once(&mut p.estimate.sum).chain(&mut p.aovs) {
+                *c = Color::new(read_f32(&mut r)?, read_f32(&mut r)?, read_f32(&mut r)?);
+            }
+            p.estimate.mean = read_f32(&mut r)?;
+            p.estimate.m2 = read_f32(&mut r)?;
+        }
+        Ok(state)
+    }
+}
+
+/// 64-bit FNV-1a hash of `bytes`. Unlike `DefaultHasher` it is the same in every build, so
+/// checkpoints can be resumed by another build of the renderer.
+pub fn fingerprint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let mut state = RenderState::new(Header {
+            width: 4,
+            height: 2,
+            seed: 7,
+            sampler: SamplerKind::Halton,
+            sampler_samples: 16,
+            scene: fingerprint(b"objects: []"),
+            settings: 5,
+            aovs: vec![Aov::Depth, Aov::ObjectId],
+            passes: 3,
+        });
+        for (i, p) in state.pixels.iter_mut().enumerate() {
+            for s in 0..=i {
+                p.estimate.add(Color::new(s as f32, 0.5, 2.0));
+            }
+            p.aovs[0] = Color::new_all(i as f32 * 1.5);
+            p.aovs[1] = Color::new_all(i as f32);
+        }
+        let path = std::env::temp_dir().join("raytracer_test_checkpoint.bin");
+        state.save(&path).unwrap();
+        let loaded = RenderState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.header, state.header);
+        for (a, b) in loaded.pixels.iter().zip(&state.pixels) {
+            assert_eq!(a.estimate.count, b.estimate.count);
+            assert_eq!(a.estimate.sum, b.estimate.sum);
+            assert_eq!(a.estimate.error(), b.estimate.error());
+            assert_eq!(a.aovs, b.aovs);
+        }
+        let compatible = |f: fn(&mut Header)| {
+            let mut header = Header {
+                aovs: vec![Aov::ObjectId],
+                ..state.header.clone()
+            };
+            f(&mut header);
+            loaded.check_compatible(&header).is_ok()
+        };
+        assert!(compatible(|_| {}));
+        assert!(compatible(|h| h.passes = 0));
+        assert!(!compatible(|h| h.height = 4));
+        assert!(!compatible(|h| h.aovs = vec![Aov::Normal]));
+        assert!(!compatible(|h| h.scene = fingerprint(b"objects: [] ")));
+        assert!(!compatible(|h| h.settings += 1));
+    }
+}
@@ -0,0 +1,184 @@
+use rayon::prelude::*;
+
+use crate::{environment::luminance, image::Image, vectors::Color};
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010), guided by the normal and
+/// albedo of the first hit. The lighting is filtered separately from the albedo, so texture
+/// detail survives.
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser {
+    /// Number of filter passes. Each doubles the distance between the taps.
+    pub iterations: u32,
+    /// How much lighting may differ between pixels that are still averaged
+    pub sigma_color: f32,
+    pub sigma_normal: f32,
+    pub sigma_albedo: f32,
+}
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 5,
+            sigma_color: 1.0,
+            sigma_normal: 0.1,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+/// B3 spline weights of the 5x5 filter kernel
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+impl Denoiser {
+    pub fn denoise(&self, color: &Image, normal: &Image, albedo: &Image) -> Image {
+        let (width, height) = (color.width() as i64, color.height() as i64);
+        let normals = normal.pixels();
+        let albedos = albedo.pixels();
+        // Divide out the albedo so the filter only has to smooth the lighting.
+        let demodulate = |c: Color, a: Color| {
+            Color::new(
+                c.x / a.x.max(1e-3),
+                c.y / a.y.max(1e-3),
+                c.z / a.z.max(1e-3),
+            )
+        };
+        let mut lighting = color
+            .pixels()
+            .iter()
+            .zip(albedos)
+            .map(|(&c, &a)| demodulate(c, a))
+            .collect::<Vec<_>>();
+        lighting = remove_fireflies(&lighting, width, height);
+
+        for iteration in 0..self.iterations {
+            let step = 1i64 << iteration;
+            // Later passes compare already smoothed values, so be stricter about them.
+            let sigma_color = self.sigma_color / 2f32.powf(iteration as f32 / 2.0);
+            let inv_color = 1.0 / (sigma_color * sigma_color);
+            let inv_normal = 1.0 / (self.sigma_normal * self.sigma_normal);
+            let inv_albedo = 1.0 / (self.sigma_albedo * self.sigma_albedo);
+            let input = lighting;
+            lighting = (0..input.len())
+                .into_par_iter()
+                .map(|i| {
+                    let (x, y) = (i as i64 % width, i as i64 / width);
+                    let (c, n, a) = (input[i], normals[i], albedos[i]);
+                    let mut sum = Color::new_all(0.0);
+                    let mut weight_sum = 0.0;
+                    for (ky, wy) in KERNEL.iter().enumerate() {
+                        let qy = y + (ky as i64 - 2) * step;
+                        if qy < 0 || qy >= height {
+                            continue;
+                        }
+                        for (kx, wx) in KERNEL.iter().enumerate() {
+                            let qx = x + (kx as i64 - 2) * step;
+                            if qx < 0 || qx >= width {
+                                continue;
+                            }
+                            let j = (qy * width + qx) as usize;
+                            let distance = |p: Color, q: Color| (p - q).length_squared();
+                            let weight = wx
+                                * wy
+                                * (-distance(c, input[j]) * inv_color
+                                    - distance(n, normals[j]) * inv_normal
+                                    - distance(a, albedos[j]) * inv_albedo)
+                                    .exp();
+                            sum = sum + input[j] * Color::new_all(weight);
+                            weight_sum += weight;
+                        }
+                    }
+                    // The center tap always has weight, so this never divides by zero.
+                    sum / Color::new_all(weight_sum)
+                })
+                .collect();
+        }
+
+        let pixels = lighting
+            .iter()
+            .zip(albedos)
+            .map(|(&l, &a)| l * Color::new(a.x.max(1e-3), a.y.max(1e-3), a.z.max(1e-3)))
+            .collect();
+        Image::from_pixels(color.width(), color.height(), pixels)
+    }
+}
+
+/// Scales down pixels much brighter than their neighbors. The edge-stopping weights would
+/// otherwise keep such outliers as they are.
+fn remove_fireflies(pixels: &[Color], width: i64, height: i64) -> Vec<Color> {
+    (0..pixels.len())
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i as i64 % width, i as i64 / width);
+            let (mut sum, mut sum2, mut count) = (0.0, 0.0, 0.0);
+            for qy in (y - 1).max(0)..=(y + 1).min(height - 1) {
+                for qx in (x - 1).max(0)..=(x + 1).min(width - 1) {
+                    if (qx, qy) != (x, y) {
+                        let l = luminance(pixels[(qy * width + qx) as usize]);
+                        sum += l;
+                        sum2 += l * l;
+                        count += 1.0;
+                    }
+                }
+            }
+            let mean = sum / count;
+            let deviation = (sum2 / count - mean * mean).max(0.0).sqrt();
+            let limit = mean + 3.0 * deviation;
+            let l = luminance(pixels[i]);
+            if l > limit && l > 0.0 {
+                pixels[i] * Color::new_all(limit / l)
+            } else {
+                pixels[i]
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_denoise_reduces_noise_and_keeps_edges() {
+        let (width, height) = (32, 32);
+        let mut rng = rand::thread_rng();
+        let mut color = Image::new(width, height);
+        let mut normal = Image::new(width, height);
+        let mut albedo = Image::new(width, height);
+        // Two flat regions facing different ways, with noisy lighting.
+        for y in 0..height {
+            for x in 0..width {
+                let left = x < width / 2;
+                let n = if left {
+                    Color::new(1.0, 0.0, 0.0)
+                } else {
+                    Color::new(0.0, 1.0, 0.0)
+                };
+                let base = if left { 0.2 } else { 0.8 };
+                let noisy = base * rng.gen_range(0.5..1.5);
+                color.set_pixel(x, y, Color::new_all(noisy), 1);
+                normal.set_pixel(x, y, n, 1);
+                albedo.set_pixel(x, y, Color::new_all(1.0), 1);
+            }
+        }
+        let result = Denoiser::default().denoise(&color, &normal, &albedo);
+        let error = |image: &Image| {
+            let mut sum = 0.0;
+            for y in 0..height {
+                for x in 0..width {
+                    let expected = if x < width / 2 { 0.2 } else { 0.8 };
+                    sum += (image.get_pixel(x, y).x - expected).powi(2);
+                }
+            }
+            sum
+        };
+        assert!(
+            error(&result) < 0.1 * error(&color),
+            "{} {}",
+            error(&result),
+            error(&color)
+        );
+        // Pixels right next to the edge keep their own side's value.
+        assert!((result.get_pixel(width / 2 - 1, 5).x - 0.2).abs() < 0.05);
+        assert!((result.get_pixel(width / 2, 5).x - 0.8).abs() < 0.1);
+    }
+}
@@ -0,0 +1,296 @@
+use std::{convert::TryFrom, fs::File, io::BufReader, path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use image::codecs::hdr::HdrDecoder;
+use serde::{Deserialize, Serialize};
+
+use crate::vectors::{Color, Vec3};
+
+const PI: f32 = std::f32::consts::PI;
+
+/// An equirectangular (latitude-longitude) HDR image lighting the scene from infinitely far away.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "EnvironmentMapDesc", into = "EnvironmentMapDesc")]
+pub struct EnvironmentMap {
+    path: PathBuf,
+    /// Rotation around the vertical axis, in degrees
+    rotation: f32,
+    intensity: f32,
+    texels: Arc<Texels>,
+}
+#[derive(Serialize, Deserialize)]
+struct EnvironmentMapDesc {
+    path: PathBuf,
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+}
+fn default_intensity() -> f32 {
+    1.0
+}
+impl TryFrom<EnvironmentMapDesc> for EnvironmentMap {
+    type Error = anyhow::Error;
+    fn try_from(desc: EnvironmentMapDesc) -> Result<Self> {
+        Ok(EnvironmentMap {
+            texels: Arc::new(Texels::load(&desc.path)?),
+            path: desc.path,
+            rotation: desc.rotation,
+            intensity: desc.intensity,
+        })
+    }
+}
+impl From<EnvironmentMap> for EnvironmentMapDesc {
+    fn from(env: EnvironmentMap) -> Self {
+        EnvironmentMapDesc {
+            path: env.path,
+            rotation: env.rotation,
+            intensity: env.intensity,
+        }
+    }
+}
+
+struct Texels {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    distribution: Distribution2D,
+}
+impl std::fmt::Debug for Texels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Texels({}x{})", self.width, self.height)
+    }
+}
+impl Texels {
+    fn load(path: &PathBuf) -> Result<Texels> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let (width, height, pixels) = match extension.as_deref() {
+            Some("hdr") => {
+                let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+                let meta = decoder.metadata();
+                let pixels = decoder
+                    .read_image_hdr()?
+                    .into_iter()
+                    .map(|p| Color::new(p[0], p[1], p[2]))
+                    .collect();
+                (meta.width as usize, meta.height as usize, pixels)
+            }
+            Some("exr") => {
+                let image = exr::prelude::read_first_rgba_layer_from_file(
+                    path,
+                    |resolution, _| {
+                        (
+                            resolution.width(),
+                            vec![Color::new_all(0.0); resolution.width() * resolution.height()],
+                        )
+                    },
+                    |(width, pixels): &mut (usize, Vec<Color>),
+                     position,
+                     (r, g, b, _a): (f32, f32, f32, f32)| {
+                        pixels[position.y() * *width + position.x()] = Color::new(r, g, b);
+                    },
+                )?;
+                let size = image.layer_data.size;
+                let (_, pixels) = image.layer_data.channel_data.pixels;
+                (size.width(), size.height(), pixels)
+            }
+            _ => {
+                return Err(anyhow!(
+                    "unsupported environment map {:?}, expected .hdr or .exr",
+                    path
+                ))
+            }
+        };
+
+        // Weight each texel by its luminance and by the solid angle it covers.
+        let weights = (0..height)
+            .flat_map(|y| {
+                let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+                pixels[y * width..(y + 1) * width]
+                    .iter()
+                    .map(move |c| luminance(*c) * sin_theta)
+            })
+            .collect::<Vec<_>>();
+        let distribution = Distribution2D::new(&weights, width, height);
+
+        Ok(Texels {
+            width,
+            height,
+            pixels,
+            distribution,
+        })
+    }
+}
+
+impl EnvironmentMap {
+    /// Radiance arriving from `direction`.
+    pub fn value(&self, direction: Vec3) -> Color {
+        let (u, v) = self.direction_to_uv(direction.normalize());
+        let x = ((u * self.texels.width as f32) as usize).min(self.texels.width - 1);
+        let y = ((v * self.texels.height as f32) as usize).min(self.texels.height - 1);
+        self.texels.pixels[y * self.texels.width + x] * Vec3::new_all(self.intensity)
+    }
+    /// Picks a direction proportionally to the luminance of the map. Returns the direction,
+    /// the radiance arriving from it and its pdf with respect to solid angle.
+    pub fn sample(&self, u1: f32, u2: f32) -> Option<(Vec3, Color, f32)> {
+        let (u, v, map_pdf) = self.texels.distribution.sample(u1, u2);
+        if map_pdf == 0.0 {
+            return None;
+        }
+        let direction = self.uv_to_direction(u, v);
+        let sin_theta = (PI * v).sin();
+        if sin_theta == 0.0 {
+            return None;
+        }
+        let pdf = map_pdf / (2.0 * PI * PI * sin_theta);
+        Some((direction, self.value(direction), pdf))
+    }
+    /// Solid angle pdf of `sample` returning `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let (u, v) = self.direction_to_uv(direction.normalize());
+        let sin_theta = (PI * v).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.texels.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn direction_to_uv(&self, d: Vec3) -> (f32, f32) {
+        let phi = d.z.atan2(d.x) + self.rotation.to_radians();
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+    fn uv_to_direction(&self, u: f32, v: f32) -> Vec3 {
+        let phi = u * 2.0 * PI - self.rotation.to_radians();
+        let theta = v * PI;
+        Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        )
+    }
+}
+
+pub fn luminance(c: Color) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Piecewise-constant 1D distribution over [0, 1).
+struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+impl Distribution1D {
+    fn new(func: &[f32]) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f32;
+        }
+        let integral = cdf[n];
+        if integral == 0.0 {
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n as f32;
+            }
+        } else {
+            for c in cdf.iter_mut() {
+                *c /= integral;
+            }
+        }
+        Distribution1D {
+            func: func.to_vec(),
+            cdf,
+            integral,
+        }
+    }
+    fn count(&self) -> usize {
+        self.func.len()
+    }
+    /// Returns the sampled position in [0, 1), its pdf and the index of its segment.
+    fn sample(&self, u: f32) -> (f32, f32, usize) {
+        // Last cdf entry that is <= u
+        let offset = match self.cdf.partition_point(|c| *c <= u) {
+            0 => 0,
+            i => (i - 1).min(self.count() - 1),
+        };
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+        let pdf = if self.integral > 0.0 {
+            self.func[offset] / self.integral
+        } else {
+            0.0
+        };
+        ((offset as f32 + du) / self.count() as f32, pdf, offset)
+    }
+    fn pdf(&self, x: f32) -> f32 {
+        if self.integral == 0.0 {
+            return 0.0;
+        }
+        let i = ((x * self.count() as f32) as usize).min(self.count() - 1);
+        self.func[i] / self.integral
+    }
+}
+
+/// Piecewise-constant 2D distribution over [0, 1)², sampled row first.
+struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+impl Distribution2D {
+    fn new(func: &[f32], width: usize, height: usize) -> Distribution2D {
+        let conditional = (0..height)
+            .map(|y| Distribution1D::new(&func[y * width..(y + 1) * width]))
+            .collect::<Vec<_>>();
+        let marginal =
+            Distribution1D::new(&conditional.iter().map(|d| d.integral).collect::<Vec<_>>());
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+    fn sample(&self, u1: f32, u2: f32) -> (f32, f32, f32) {
+        let (v, pdf_v, row) = self.marginal.sample(u2);
+        let (u, pdf_u, _) = self.conditional[row].sample(u1);
+        (u, v, pdf_u * pdf_v)
+    }
+    fn pdf(&self, u: f32, v: f32) -> f32 {
+        let row = ((v * self.marginal.count() as f32) as usize).min(self.marginal.count() - 1);
+        self.marginal.pdf(v) * self.conditional[row].pdf(u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::approx_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_distribution_pdf_matches_sample() {
+        let func = [1.0, 0.0, 3.0, 0.0, 2.0, 2.0];
+        let distribution = Distribution2D::new(&func, 3, 2);
+        for &(u1, u2) in &[(0.1, 0.2), (0.7, 0.4), (0.5, 0.9), (0.99, 0.6)] {
+            let (u, v, pdf) = distribution.sample(u1, u2);
+            assert!(pdf > 0.0);
+            assert!(approx_eq!(f32, distribution.pdf(u, v), pdf, epsilon = 1e-5));
+        }
+    }
+
+    #[test]
+    fn test_distribution_skips_empty_texels() {
+        let distribution = Distribution1D::new(&[0.0, 1.0, 0.0, 1.0]);
+        for i in 0..16 {
+            let (x, pdf, offset) = distribution.sample(i as f32 / 16.0);
+            assert!(offset == 1 || offset == 3, "sampled empty segment at {}", x);
+            assert!(approx_eq!(f32, pdf, 2.0, ulps = 4));
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    aabb::Aabb,
+    hittable::Hittable,
+    material::{pbr::Pbr, DiffuseLight, Material},
+    shapes::mesh::{Mesh, MeshData},
+    texture::{ColorInput, Filter, ImageTexture, Texels, WrapMode},
+    vectors::{Color, Point3, Vec3},
+};
+
+/// The first camera found while walking the node hierarchy.
+pub struct GltfCamera {
+    pub pos: Point3,
+    /// Point straight ahead at the depth of the center of the scene, so that the distance
+    /// to it is a sensible focus distance. glTF cameras have none of their own.
+    pub lookat: Point3,
+    /// Vertical field of view in degrees
+    pub fov: f32,
+}
+pub struct GltfScene {
+    pub objects: Vec<Box<dyn Hittable>>,
+    pub camera: Option<GltfCamera>,
+}
+
+/// Column-major 4x4 matrix, as stored by glTF.
+type Mat4 = [[f32; 4]; 4];
+const IDENTITY: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+fn mul(a: &Mat4, b: &Mat4) -> Mat4 {
+    let mut m = [[0.0; 4]; 4];
+    for (col, m_col) in m.iter_mut().enumerate() {
+        for (row, value) in m_col.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b[col][k]).sum();
+        }
+    }
+    m
+}
+fn transform_point(m: &Mat4, p: Point3) -> Point3 {
+    Point3::new(
+        m[0][0] * p.x + m[1][0] * p.y + m[2][0] * p.z + m[3][0],
+        m[0][1] * p.x + m[1][1] * p.y + m[2][1] * p.z + m[3][1],
+        m[0][2] * p.x + m[1][2] * p.y + m[2][2] * p.z + m[3][2],
+    )
+}
+fn transform_vector(m: &Mat4, v: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x + m[1][0] * v.y + m[2][0] * v.z,
+        m[0][1] * v.x + m[1][1] * v.y + m[2][1] * v.z,
+        m[0][2] * v.x + m[1][2] * v.y + m[2][2] * v.z,
+    )
+}
+/// Inverse transpose of the upper 3x3 block, for transforming normals, and its determinant.
+fn normal_matrix(m: &Mat4) -> (Mat4, f32) {
+    let a = |row: usize, col: usize| m[col][row];
+    let cofactor = |row: usize, col: usize| {
+        let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+        let (c0, c1) = ((col + 1) % 3, (col + 2) % 3);
+        a(r0, c0) * a(r1, c1) - a(r0, c1) * a(r1, c0)
+    };
+    let det = (0..3).map(|col| a(0, col) * cofactor(0, col)).sum::<f32>();
+    // The inverse is the transposed cofactor matrix over the determinant, so the
+    // inverse transpose is the cofactor matrix itself.
+    let mut n = IDENTITY;
+    for (col, n_col) in n.iter_mut().enumerate().take(3) {
+        for (row, value) in n_col.iter_mut().enumerate().take(3) {
+            *value = cofactor(row, col) / det;
+        }
+    }
+    (n, det)
+}
+
+pub fn import(path: &Path) -> Result<GltfScene> {
+    let (document, buffers, images) =
+        gltf::import(path).with_context(|| format!("failed to import {:?}", path))?;
+    // Normal and metallic-roughness maps hold data rather than colors, so they are not
+    // sRGB-decoded.
+    let data_images = document
+        .materials()
+        .flat_map(|m| {
+            let normal = m.normal_texture().map(|n| n.texture());
+            let metallic_roughness = m
+                .pbr_metallic_roughness()
+                .metallic_roughness_texture()
+                .map(|info| info.texture());
+            normal.into_iter().chain(metallic_roughness)
+        })
+        .map(|texture| texture.source().index())
+        .collect::<Vec<_>>();
+    let textures = images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            let width = image.width as usize;
+            let height = image.height as usize;
+            use gltf::image::Format;
+            let (channels, bytes_per_channel) = match image.format {
+                Format::R8 => (1, 1),
+                Format::R8G8 => (2, 1),
+                Format::R8G8B8 | Format::B8G8R8 => (3, 1),
+                Format::R8G8B8A8 | Format::B8G8R8A8 => (4, 1),
+                Format::R16 => (1, 2),
+                Format::R16G16 => (2, 2),
+                Format::R16G16B16 => (3, 2),
+                Format::R16G16B16A16 => (4, 2),
+            };
+            // Keep the most significant byte of 16-bit channels (stored little-endian).
+            let mut data = image
+                .pixels
+                .iter()
+                .skip(bytes_per_channel - 1)
+                .step_by(bytes_per_channel)
+                .copied()
+                .collect::<Vec<_>>();
+            if let Format::B8G8R8 | Format::B8G8R8A8 = image.format {
+                for pixel in data.chunks_exact_mut(channels) {
+                    pixel.swap(0, 2);
+                }
+            }
+            let srgb = !data_images.contains(&i);
+            Arc::new(Texels::from_8bit(width, height, channels, &data, srgb))
+        })
+        .collect::<Vec<_>>();
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| anyhow!("{:?} contains no scene", path))?;
+    let mut imported = GltfScene {
+        objects: Vec::new(),
+        camera: None,
+    };
+    for node in scene.nodes() {
+        visit(node, &IDENTITY, &buffers, &textures, &mut imported)?;
+    }
+    if let Some(camera) = &mut imported.camera {
+        let bounds = imported
+            .objects
+            .iter()
+            .filter_map(|object| object.bounding_box())
+            .fold(Aabb::empty(), |bounds, bbox| bounds.surrounding(bbox));
+        camera.lookat = focus_point(camera.pos, camera.lookat - camera.pos, bounds);
+    }
+    Ok(imported)
+}
+
+/// Point along the unit `forward` direction from `pos` at the depth of the center of
+/// `bounds`, or one unit ahead if the scene is empty or behind the camera.
+fn focus_point(pos: Point3, forward: Vec3, bounds: Aabb) -> Point3 {
+    let depth = (bounds.centroid() - pos).dot(forward);
+    let depth = if depth.is_finite() && depth > 0.0 {
+        depth
+    } else {
+        1.0
+    };
+    pos + forward * Vec3::new_all(depth)
+}
+
+/// Texture coordinate set used by the material's textures. Meshes here have a single set,
+/// so only the base color's is honored when they differ.
+fn tex_coord_set(material: &gltf::Material) -> u32 {
+    let pbr = material.pbr_metallic_roughness();
+    let sets = [
+        pbr.base_color_texture().map(|info| info.tex_coord()),
+        pbr.metallic_roughness_texture()
+            .map(|info| info.tex_coord()),
+        material.normal_texture().map(|normal| normal.tex_coord()),
+    ];
+    let mut sets = sets.iter().flatten();
+    let set = sets.next().copied().unwrap_or(0);
+    if sets.any(|&other| other != set) {
+        eprintln!(
+            "Warning: material {} uses several texture coordinate sets, using set {} for all",
+            material.name().unwrap_or("(unnamed)"),
+            set
+        );
+    }
+    set
+}
+
+fn visit(
+    node: gltf::Node,
+    parent: &Mat4,
+    buffers: &[gltf::buffer::Data],
+    textures: &[Arc<Texels>],
+    imported: &mut GltfScene,
+) -> Result<()> {
+    let transform = mul(parent, &node.transform().matrix());
+
+    if let (Some(camera), None) = (node.camera(), &imported.camera) {
+        match camera.projection() {
+            gltf::camera::Projection::Perspective(perspective) => {
+                // glTF cameras look down their local -Z axis.
+                let pos = transform_point(&transform, Point3::new_all(0.0));
+                let forward = transform_vector(&transform, Vec3::new(0.0, 0.0, -1.0));
+                imported.camera = Some(GltfCamera {
+                    pos,
+                    lookat: pos + forward.normalize(),
+                    fov: perspective.yfov().to_degrees(),
+                });
+            }
+            gltf::camera::Projection::Orthographic(_) => {
+                eprintln!("Warning: skipping orthographic glTF camera");
+            }
+        }
+    }
+
+    if let Some(mesh) = node.mesh() {
+        let (normal_transform, det) = normal_matrix(&transform);
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                eprintln!(
+                    "Warning: skipping {:?} primitive in mesh {}",
+                    primitive.mode(),
+                    mesh.name().unwrap_or("(unnamed)")
+                );
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions = match reader.read_positions() {
+                Some(positions) => positions
+                    .map(|p| transform_point(&transform, Point3::new(p[0], p[1], p[2])))
+                    .collect::<Vec<_>>(),
+                None => continue,
+            };
+            let normals = reader
+                .read_normals()
+                .map(|normals| {
+                    normals
+                        .map(|n| {
+                            transform_vector(&normal_transform, Vec3::new(n[0], n[1], n[2]))
+                                .normalize()
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            let uvs = reader
+                .read_tex_coords(tex_coord_set(&primitive.material()))
+                .map(|uvs| uvs.into_f32().map(|uv| (uv[0], uv[1])).collect())
+                .unwrap_or_default();
+            let flat_indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+                None => (0..positions.len()).collect::<Vec<_>>(),
+            };
+            let indices = flat_indices
+                .chunks_exact(3)
+                .map(|i| {
+                    // Mirroring transforms flip the winding, and with it the front face.
+                    if det < 0.0 {
+                        [i[0], i[2], i[1]]
+                    } else {
+                        [i[0], i[1], i[2]]
+                    }
+                })
+                .collect();
+
+            let data = MeshData {
+                positions,
+                normals,
+                uvs,
+                indices,
+            };
+            let material = convert_material(&primitive.material(), textures);
+            imported
+                .objects
+                .push(Box::new(Mesh::new(data, material).with_context(|| {
+                    format!("in mesh {}", mesh.name().unwrap_or("(unnamed)"))
+                })?));
+        }
+    }
+
+    for child in node.children() {
+        visit(child, &transform, buffers, textures, imported)?;
+    }
+    Ok(())
+}
+
+/// Converts a metallic-roughness material to a `Pbr` one, or to a light if it is emissive.
+fn convert_material(material: &gltf::Material, textures: &[Arc<Texels>]) -> Box<dyn Material> {
+    let emissive = material.emissive_factor();
+    if emissive != [0.0; 3] {
+        return Box::new(DiffuseLight::new(Color::new(
+            emissive[0],
+            emissive[1],
+            emissive[2],
+        )));
+    }
+    let gltf_pbr = material.pbr_metallic_roughness();
+    let base = gltf_pbr.base_color_factor();
+    let base_color = Color::new(base[0], base[1], base[2]);
+    let mut pbr = Pbr::new(
+        base_color,
+        gltf_pbr.metallic_factor(),
+        gltf_pbr.roughness_factor(),
+    );
+    if let Some(info) = gltf_pbr.base_color_texture() {
+        let texture = image_texture(&info.texture(), textures, base_color);
+        pbr.base_color = ColorInput::Texture(Box::new(texture));
+    }
+    if let Some(info) = gltf_pbr.metallic_roughness_texture() {
+        let texture = image_texture(&info.texture(), textures, Color::new_all(1.0));
+        pbr.metallic_roughness = Some(Box::new(texture));
+    }
+    if let Some(normal) = material.normal_texture() {
+        let texture = image_texture(&normal.texture(), textures, Color::new_all(1.0));
+        pbr.normals.normal_map = Some(Box::new(texture));
+        pbr.normals.normal_scale = normal.scale();
+    }
+    Box::new(pbr)
+}
+
+/// Image texture with the wrap mode and filter of the glTF sampler. Textures wrap the same
+/// way along both axes here, so the mode along u is used for both.
+fn image_texture(texture: &gltf::Texture, textures: &[Arc<Texels>], factor: Color) -> ImageTexture {
+    use gltf::texture::{MagFilter, WrappingMode};
+    let sampler = texture.sampler();
+    let wrap = match sampler.wrap_s() {
+        WrappingMode::ClampToEdge => WrapMode::Clamp,
+        WrappingMode::MirroredRepeat => WrapMode::Mirror,
+        WrappingMode::Repeat => WrapMode::Repeat,
+    };
+    let filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => Filter::Nearest,
+        _ => Filter::Bilinear,
+    };
+    ImageTexture::new(
+        textures[texture.source().index()].clone(),
+        wrap,
+        filter,
+        factor,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normal_matrix_undoes_scale() {
+        let scale = [
+            [2.0, 0.0, 0.0, 0.0],
+            [0.0, 4.0, 0.0, 0.0],
+            [0.0, 0.0, -1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        let (n, det) = normal_matrix(&scale);
+        assert_eq!(det, -8.0);
+        assert_eq!(
+            transform_vector(&n, Vec3::new(1.0, 1.0, 1.0)),
+            Vec3::new(0.5, 0.25, -1.0)
+        );
+    }
+
+    #[test]
+    fn test_transform_composition() {
+        let mut translate = IDENTITY;
+        translate[3] = [1.0, 2.0, 3.0, 1.0];
+        let mut scale = IDENTITY;
+        scale[0][0] = 2.0;
+        // Scale first, then translate.
+        let m = mul(&translate, &scale);
+        assert_eq!(
+            transform_point(&m, Point3::new(1.0, 1.0, 1.0)),
+            Point3::new(3.0, 3.0, 4.0)
+        );
+    }
+
+    #[test]
+    fn test_tex_coord_set() {
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "images": [{"uri": "checker.png"}],
+            "textures": [{"source": 0}],
+            "materials": [
+                {"pbrMetallicRoughness": {"baseColorTexture": {"index": 0, "texCoord": 1}}},
+                {"normalTexture": {"index": 0, "texCoord": 1}},
+                {"pbrMetallicRoughness": {"baseColorFactor": [1, 0, 0, 1]}}
+            ]
+        }"#;
+        let gltf = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+        let sets = gltf
+            .materials()
+            .map(|m| tex_coord_set(&m))
+            .collect::<Vec<_>>();
+        assert_eq!(sets, [1, 1, 0]);
+    }
+
+    #[test]
+    fn test_camera_focuses_on_scene() {
+        let scene = import(Path::new("scenes/models/checker_quad.gltf")).unwrap();
+        let camera = scene.camera.unwrap();
+        // The camera is 4 units in front of the floor, looking slightly down at it.
+        let distance = (camera.lookat - camera.pos).length();
+        assert!(distance > 3.0 && distance < 5.0, "distance {}", distance);
+        assert_eq!(
+            focus_point(camera.pos, Vec3::new(0.0, 0.0, 1.0), Aabb::empty()),
+            camera.pos + Vec3::new(0.0, 0.0, 1.0)
+        );
+    }
+}
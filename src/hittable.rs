@@ -1,6 +1,7 @@
 use std::fmt::Debug;
 
 use crate::{
+    aabb::Aabb,
     material::{Diffuse, Material},
     ray::Ray,
     vectors::{Point3, Vec3},
@@ -10,8 +11,18 @@ pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
     pub t: f32,
+    /// Surface coordinates of the hit point
+    pub u: f32,
+    pub v: f32,
+    /// Derivatives of the hit point along u and v, spanning the tangent plane. Normal and
+    /// bump maps are oriented by them.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub front_face: bool,
     pub mat: Box<dyn Material>,
+    /// Number of the material among the scene's materials, from 1, or 0 if the object was
+    /// never given ids by `Hittable::assign_material_ids`
+    pub material_id: u32,
 }
 impl HitRecord {
     pub fn empty() -> HitRecord {
@@ -19,23 +30,66 @@ impl HitRecord {
             p: Point3::new(0.0, 0.0, 0.0),
             normal: Vec3::new(0.0, 0.0, 0.0),
             t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
             front_face: false,
             mat: Box::new(Diffuse::empty()),
+            material_id: 0,
         }
     }
     #[inline(always)]
     pub fn set_face_normal(&mut self, r: Ray, outward_normal: Vec3) {
-        self.front_face = r.direction.dot(outward_normal) < 0.0;
+        self.set_shading_normal(r, outward_normal, outward_normal)
+    }
+    /// Sets `front_face` from the geometric normal, and `normal` to the shading normal
+    /// facing against the ray.
+    pub fn set_shading_normal(&mut self, r: Ray, geometric_normal: Vec3, shading_normal: Vec3) {
+        self.front_face = r.direction.dot(geometric_normal) < 0.0;
         self.normal = if self.front_face {
-            outward_normal
+            shading_normal
         } else {
-            -outward_normal
+            -shading_normal
         }
     }
+    /// Perturbs `normal` by the material's normal or bump map. Objects leave this out of
+    /// `hit`, and `HittableList::hit` and `hit_object` apply it once to the closest hit
+    /// rather than to every candidate.
+    pub fn apply_normal_map(&mut self) {
+        let outward = if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        };
+        let normal = self.mat.shading_normal(self, outward);
+        self.normal = if self.front_face { normal } else { -normal }
+    }
 }
 #[typetag::serde(tag = "type")]
 pub trait Hittable: Send + Sync + HittableClone + Debug {
+    /// Closest hit between `t_min` and `t_max`. Its normal is the geometric or interpolated
+    /// one: the material's normal map is only applied by `HittableList`, so anything shading
+    /// a hit from an object directly must call `HitRecord::apply_normal_map` itself.
     fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    /// Box enclosing the whole object, or `None` if it is unbounded.
+    fn bounding_box(&self) -> Option<Aabb>;
+    /// Whether the object emits light and supports `sample`, so it is used for direct
+    /// lighting.
+    fn is_light(&self) -> bool {
+        false
+    }
+    /// Picks a direction from `origin` towards a point on the object, using the uniform
+    /// numbers `u1` and `u2`. Returns the direction and its solid angle pdf.
+    fn sample(&self, _origin: Point3, _u1: f32, _u2: f32) -> Option<(Vec3, f32)> {
+        None
+    }
+    /// Solid angle pdf of `sample` returning `direction` from `origin`.
+    fn pdf(&self, _origin: Point3, _direction: Vec3) -> f32 {
+        0.0
+    }
+    /// Numbers each of the object's materials, taking ids from `next_id` upwards.
+    fn assign_material_ids(&mut self, _next_id: &mut u32) {}
 }
 pub trait HittableClone {
     fn clone_box(&self) -> Box<dyn Hittable>;
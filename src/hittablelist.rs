@@ -1,9 +1,23 @@
+use std::{
+    fs::read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    aov::Aov,
+    background::Background,
+    bvh::{Bvh, SplitMethod},
+    camera::Projection,
+    gltf_import,
     hittable::{HitRecord, Hittable},
+    integrator::{path::PathTracer, Integrator},
     ray::Ray,
-    vectors::Vec3,
+    sampler::SamplerKind,
+    tonemap::Display,
+    vectors::{Point3, Vec3},
 };
 #[derive(Serialize, Deserialize, Debug)]
 pub struct HittableList {
@@ -11,6 +25,51 @@ pub struct HittableList {
     pub camera_pos: Vec3,
     pub camera_lookat: Vec3,
     pub camera_fov: f32,
+    /// Lens diameter for depth of field. 0 keeps everything in focus.
+    #[serde(default)]
+    pub camera_aperture: f32,
+    /// Distance from the camera to the plane in focus. Defaults to the distance to
+    /// `camera_lookat`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_focus_distance: Option<f32>,
+    #[serde(default)]
+    pub camera_projection: Projection,
+    #[serde(default)]
+    pub background: Background,
+    /// Exposure and tone mapping for 8-bit output
+    #[serde(default)]
+    pub display: Display,
+    /// Rendering algorithm, the path tracer with light sampling by default
+    #[serde(default = "default_integrator")]
+    pub integrator: Box<dyn Integrator>,
+    /// Where the random numbers for each pixel sample come from
+    #[serde(default)]
+    pub sampler: SamplerKind,
+    /// Extra passes written next to the rendered image
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aovs: Vec<Aov>,
+    /// glTF scenes whose meshes are added to `objects` by `load`. The first camera found
+    /// in them replaces `camera_pos`, `camera_lookat` and `camera_fov`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gltf: Vec<PathBuf>,
+    /// Acceleration structure over the bounded objects, built by `build_bvh`
+    #[serde(skip)]
+    bvh: Option<Bvh>,
+    /// Indices of objects without a bounding box, which are always tested
+    #[serde(skip)]
+    unbounded: Vec<usize>,
+    /// Indices of the objects sampled for direct lighting, found by `collect_lights`
+    #[serde(skip)]
+    lights: Vec<usize>,
+    /// Id given to the next material by `add`, so objects keep their ids when cloned
+    #[serde(skip, default = "first_material_id")]
+    next_material_id: u32,
+}
+fn default_integrator() -> Box<dyn Integrator> {
+    Box::new(PathTracer::default())
+}
+fn first_material_id() -> u32 {
+    1
 }
 impl HittableList {
     pub fn new() -> HittableList {
@@ -19,26 +78,163 @@ impl HittableList {
             camera_lookat: Vec3::new(0.0, 0.0, 0.0),
             camera_pos: Vec3::new(0.0, 0.0, 0.0),
             camera_fov: 0.0,
+            camera_aperture: 0.0,
+            camera_focus_distance: None,
+            camera_projection: Projection::default(),
+            background: Background::default(),
+            display: Display::default(),
+            integrator: default_integrator(),
+            sampler: SamplerKind::default(),
+            aovs: Vec::new(),
+            gltf: Vec::new(),
+            bvh: None,
+            unbounded: Vec::new(),
+            lights: Vec::new(),
+            next_material_id: first_material_id(),
         }
     }
-    pub fn add(&mut self, object: Box<dyn Hittable>) {
+    /// Reads a scene file and imports the glTF scenes it references.
+    pub fn load(path: &Path) -> Result<HittableList> {
+        let mut world = serde_yaml::from_slice::<HittableList>(&read(path)?)?;
+        for object in &mut world.objects {
+            object.assign_material_ids(&mut world.next_material_id);
+        }
+        let mut found_camera = false;
+        for gltf in world.gltf.clone() {
+            let scene = gltf_import::import(&gltf)?;
+            if let (Some(camera), false) = (scene.camera, found_camera) {
+                world.camera_pos = camera.pos;
+                world.camera_lookat = camera.lookat;
+                world.camera_fov = camera.fov;
+                found_camera = true;
+            }
+            for object in scene.objects {
+                world.add(object);
+            }
+        }
+        Ok(world)
+    }
+    pub fn focus_distance(&self) -> f32 {
+        self.camera_focus_distance
+            .unwrap_or_else(|| (self.camera_lookat - self.camera_pos).length())
+    }
+    /// Appends `object`, numbering its materials after those already in the list.
+    pub fn add(&mut self, mut object: Box<dyn Hittable>) {
+        object.assign_material_ids(&mut self.next_material_id);
         self.objects.push(object);
+        self.bvh = None;
+    }
+    /// Builds a BVH over the objects so `hit` no longer tests every one of them.
+    /// Returns the number of nodes in the hierarchy.
+    pub fn build_bvh(&mut self, split: SplitMethod) -> usize {
+        let mut bounded = Vec::new();
+        let mut boxes = Vec::new();
+        self.unbounded.clear();
+        for (i, object) in self.objects.iter().enumerate() {
+            match object.bounding_box() {
+                Some(bbox) => {
+                    bounded.push(i);
+                    boxes.push(bbox);
+                }
+                None => self.unbounded.push(i),
+            }
+        }
+        let bvh = Bvh::new(&boxes, split);
+        let node_count = bvh.node_count();
+        // Point the BVH at the objects themselves rather than at the bounded subset.
+        self.bvh = Some(bvh.remap(&bounded));
+        node_count
+    }
+    /// Finds the lights used for direct lighting: every emissive object that can be sampled.
+    /// Returns how many there are.
+    pub fn collect_lights(&mut self) -> usize {
+        self.lights = (0..self.objects.len())
+            .filter(|&i| self.objects[i].is_light())
+            .collect();
+        self.lights.len()
+    }
+    /// Picks one of the lights uniformly and samples a direction towards it from `origin`.
+    /// Returns the light's index, the direction and its pdf, which includes the chance of
+    /// picking the light.
+    pub fn sample_light(
+        &self,
+        origin: Point3,
+        u: f32,
+        u1: f32,
+        u2: f32,
+    ) -> Option<(usize, Vec3, f32)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let light =
+            self.lights[((u * self.lights.len() as f32) as usize).min(self.lights.len() - 1)];
+        let (direction, pdf) = self.objects[light].sample(origin, u1, u2)?;
+        Some((light, direction, pdf / self.lights.len() as f32))
+    }
+    /// Pdf of `sample_light` returning `direction` towards the object with index `object`.
+    pub fn light_pdf(&self, object: usize, origin: Point3, direction: Vec3) -> f32 {
+        if self.lights.binary_search(&object).is_err() {
+            return 0.0;
+        }
+        self.objects[object].pdf(origin, direction) / self.lights.len() as f32
     }
     pub fn hit(&self, r: Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
-        let mut hit_anything = false;
+        match self.hit_object(r, t_min, t_max) {
+            Some((_, rec2)) => {
+                *rec = rec2;
+                true
+            }
+            None => false,
+        }
+    }
+    /// Like `hit`, but also returns the index of the object that was hit. The normal of the
+    /// returned record includes the material's normal or bump map.
+    pub fn hit_object(&self, r: Ray, t_min: f32, t_max: f32) -> Option<(usize, HitRecord)> {
+        let (object, mut rec) = self.closest_hit(r, t_min, t_max)?;
+        rec.apply_normal_map();
+        Some((object, rec))
+    }
+    /// Whether anything is hit between `t_min` and `t_max`, for shadow rays that need no
+    /// shading.
+    pub fn occluded(&self, r: Ray, t_min: f32, t_max: f32) -> bool {
+        self.closest_hit(r, t_min, t_max).is_some()
+    }
+    fn closest_hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<(usize, HitRecord)> {
+        let bvh = match &self.bvh {
+            Some(bvh) => bvh,
+            None => return self.hit_linear(r, t_min, t_max),
+        };
         let mut closest_so_far = t_max;
-        for object in &self.objects {
-            if let Some(rec2) = object.hit(r, t_min, closest_so_far) {
-                rec.p = rec2.p;
-                rec.t = rec2.t;
-                rec.normal = rec2.normal;
-                rec.front_face = rec2.front_face;
-                rec.mat = rec2.mat;
-                hit_anything = true;
+        let mut result = None;
+        for &i in &self.unbounded {
+            if let Some(rec) = self.objects[i].hit(r, t_min, closest_so_far) {
                 closest_so_far = rec.t;
+                result = Some((i, rec));
+            }
+        }
+        // Every hit the BVH reports is closer than the ones before it.
+        let mut hit_index = 0;
+        if let Some(rec) = bvh.hit(r, t_min, closest_so_far, |i, t_min, t_max| {
+            let rec = self.objects[i].hit(r, t_min, t_max);
+            if rec.is_some() {
+                hit_index = i;
             }
+            rec
+        }) {
+            result = Some((hit_index, rec));
         }
-        hit_anything
+        result
+    }
+    fn hit_linear(&self, r: Ray, t_min: f32, t_max: f32) -> Option<(usize, HitRecord)> {
+        let mut result = None;
+        let mut closest_so_far = t_max;
+        for (i, object) in self.objects.iter().enumerate() {
+            if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                result = Some((i, rec));
+            }
+        }
+        result
     }
 }
 impl Clone for HittableList {
@@ -48,6 +244,83 @@ impl Clone for HittableList {
             camera_lookat: self.camera_lookat,
             camera_pos: self.camera_pos,
             camera_fov: self.camera_fov,
+            camera_aperture: self.camera_aperture,
+            camera_focus_distance: self.camera_focus_distance,
+            camera_projection: self.camera_projection,
+            background: self.background.clone(),
+            display: self.display,
+            integrator: self.integrator.clone_box(),
+            sampler: self.sampler,
+            aovs: self.aovs.clone(),
+            gltf: self.gltf.clone(),
+            bvh: self.bvh.clone(),
+            unbounded: self.unbounded.clone(),
+            lights: self.lights.clone(),
+            next_material_id: self.next_material_id,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::{material::Diffuse, shapes::sphere::Sphere};
+
+    #[test]
+    fn test_bvh_matches_linear() {
+        let mut rng = rand::thread_rng();
+        let mut world = HittableList::new();
+        for _ in 0..200 {
+            world.add(Box::new(Sphere::new(
+                Vec3::random_range(-20.0, 20.0),
+                rng.gen_range(0.1..2.0),
+                Box::new(Diffuse::empty()),
+            )));
+        }
+        let mut bvh_world = world.clone();
+        for split in &[SplitMethod::Sah, SplitMethod::Midpoint] {
+            bvh_world.build_bvh(*split);
+            for _ in 0..1000 {
+                let r = Ray::new(Vec3::random_range(-25.0, 25.0), Vec3::random_unit_vector());
+                let mut linear = HitRecord::empty();
+                let mut accelerated = HitRecord::empty();
+                assert_eq!(
+                    world.hit(r, 0.001, f32::MAX, &mut linear),
+                    bvh_world.hit(r, 0.001, f32::MAX, &mut accelerated)
+                );
+                assert_eq!(linear.t, accelerated.t);
+                assert_eq!(linear.p, accelerated.p);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bvh_with_nan_bounds() {
+        // Degenerate input, such as a mesh with NaN vertices, must not stop the build.
+        let mut world = HittableList::new();
+        for _ in 0..8 {
+            world.add(Box::new(Sphere::new(
+                Vec3::new_all(f32::NAN),
+                1.0,
+                Box::new(Diffuse::empty()),
+            )));
+        }
+        for split in &[SplitMethod::Sah, SplitMethod::Midpoint] {
+            world.build_bvh(*split);
+        }
+        for i in 0..8 {
+            world.add(Box::new(Sphere::new(
+                Vec3::new(3.0 * i as f32, 0.0, 0.0),
+                1.0,
+                Box::new(Diffuse::empty()),
+            )));
+        }
+        for split in &[SplitMethod::Sah, SplitMethod::Midpoint] {
+            world.build_bvh(*split);
+            let r = Ray::new(Vec3::new(6.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+            let (_, rec) = world.hit_object(r, 0.001, f32::MAX).unwrap();
+            assert_eq!(rec.t, 4.0);
         }
     }
 }
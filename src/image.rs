@@ -1,58 +1,181 @@
-use crate::vectors::Color;
-use ::image::{ImageBuffer, RgbImage};
-use anyhow::Result;
-use image::Rgb;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
 
+use crate::{tonemap::Display, vectors::Color};
+use ::image::{codecs::hdr::HdrEncoder, ImageBuffer, Rgb, RgbImage};
+use anyhow::{Context, Result};
+
+/// Linear radiance framebuffer. Rows are stored top to bottom, while `x` and `y` in the
+/// accessors count from the bottom left like the camera does.
 pub struct Image {
-    pub image: RgbImage,
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
 }
 
 impl Image {
     pub fn new(width: u32, height: u32) -> Image {
         Image {
-            image: ImageBuffer::new(width, height),
+            width,
+            height,
+            pixels: vec![Color::new_all(0.0); width as usize * height as usize],
+        }
+    }
+    /// Wraps `pixels` stored top row first.
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<Color>) -> Image {
+        assert_eq!(pixels.len(), width as usize * height as usize);
+        Image {
+            width,
+            height,
+            pixels,
         }
     }
     pub fn width(&self) -> u32 {
-        self.image.width()
+        self.width
     }
     pub fn height(&self) -> u32 {
-        self.image.height()
+        self.height
+    }
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((self.height - 1 - y) * self.width + x) as usize
     }
 
+    /// Stores the average of `samples_per_pixel` samples summing to `color`.
     pub fn set_pixel(&mut self, x: u32, y: u32, color: Color, samples_per_pixel: u32) {
-        let mut r = color.x;
-        let mut g = color.y;
-        let mut b = color.z;
+        let index = self.index(x, y);
+        self.pixels[index] = color / Color::new_all(samples_per_pixel as f32);
+    }
+    pub fn get_pixel(&self, x: u32, y: u32) -> Color {
+        self.pixels[self.index(x, y)]
+    }
 
-        // Divide the color by the number of samples and gamma-correct for gamma=2.0.
-        let scale = 1.0 / (samples_per_pixel as f32);
-        r = (scale * r).sqrt();
-        g = (scale * g).sqrt();
-        b = (scale * b).sqrt();
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+    /// Applies `f` to every pixel.
+    pub fn map(&self, f: impl Fn(Color) -> Color) -> Image {
+        Image {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(|&c| f(c)).collect(),
+        }
+    }
 
-        self.image.put_pixel(
-            x,
-            (self.height() - 1) - y,
-            Rgb([
-                (r * 255.999) as u8,
-                (g * 255.999) as u8,
-                (b * 255.999) as u8,
-            ]),
+    /// Saves the image in the format given by the extension of `path`. `.exr`, `.hdr` and
+    /// `.pfm` files hold the unclamped linear radiance; anything else is written by the
+    /// `image` crate after the `display` transform.
+    pub fn save(&self, path: &str, display: &Display) -> Result<()> {
+        self.save_with(path, |c| display.apply(c))
+    }
+    /// Like `save`, but 8-bit formats get the values clamped to [0, 1] without any
+    /// display transform, for data that is not radiance.
+    pub fn save_data(&self, path: &str) -> Result<()> {
+        self.save_with(path, |c| {
+            Color::new(
+                c.x.clamp(0.0, 1.0),
+                c.y.clamp(0.0, 1.0),
+                c.z.clamp(0.0, 1.0),
+            )
+        })
+    }
+    fn save_with(&self, path: &str, encode: impl Fn(Color) -> Color) -> Result<()> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("exr") => self.save_exr(path),
+            Some("hdr") => self.save_hdr(path),
+            Some("pfm") => self.save_pfm(path),
+            _ => Ok(self.to_rgb8(encode).save(path)?),
+        }
+        .with_context(|| format!("failed to save {}", path))
+    }
+    /// Saves an OpenEXR file with this image as its RGB channels and the `layers` as extra
+    /// channels named `<layer>.<channel>`. Each layer lists the names of the channels it
+    /// takes from the components of its image.
+    pub fn save_exr_with_layers(
+        &self,
+        path: &str,
+        layers: &[(&str, &[&str], &Image)],
+    ) -> Result<()> {
+        use exr::prelude::{
+            AnyChannel, AnyChannels, Encoding, FlatSamples, LayerAttributes, SmallVec, Text,
+            WritableImage,
+        };
+        let channel = |name: String, image: &Image, component: usize| {
+            AnyChannel::new(
+                Text::from(name.as_str()),
+                FlatSamples::F32(image.pixels.iter().map(|c| c.axis(component)).collect()),
+            )
+        };
+        let mut channels = (0..3)
+            .map(|i| channel(["R", "G", "B"][i].to_string(), self, i))
+            .collect::<Vec<_>>();
+        for (layer, names, image) in layers {
+            for (i, name) in names.iter().enumerate() {
+                channels.push(channel(format!("{}.{}", layer, name), image, i));
+            }
+        }
+        let layer = exr::prelude::Layer::new(
+            (self.width as usize, self.height as usize),
+            LayerAttributes::default(),
+            Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(SmallVec::from_vec(channels)),
         );
+        exr::prelude::Image::from_layer(layer)
+            .write()
+            .to_file(path)
+            .with_context(|| format!("failed to save {}", path))?;
+        Ok(())
     }
-    pub fn get_pixel(&self, x: u32, y: u32) -> Color {
-        let pixel = self.image.get_pixel(x, y);
-        Color::new(
-            (pixel[0] as f32) / 255.999,
-            (pixel[1] as f32) / 255.999,
-            (pixel[2] as f32) / 255.999,
-        )
-    }
-    pub fn save(&self, path: &str) -> Result<()> {
-        self.image.save(path)?;
+    fn save_exr(&self, path: &str) -> Result<()> {
+        let width = self.width as usize;
+        exr::prelude::write_rgb_file(path, width, self.height as usize, |x, y| {
+            let c = self.pixels[y * width + x];
+            (c.x, c.y, c.z)
+        })?;
+        Ok(())
+    }
+    fn save_hdr(&self, path: &str) -> Result<()> {
+        let data = self
+            .pixels
+            .iter()
+            .map(|c| Rgb([c.x, c.y, c.z]))
+            .collect::<Vec<_>>();
+        HdrEncoder::new(BufWriter::new(File::create(path)?)).encode(
+            &data,
+            self.width as usize,
+            self.height as usize,
+        )?;
         Ok(())
     }
+    /// Portable float map: a short text header followed by little-endian floats, with
+    /// rows from bottom to top.
+    fn save_pfm(&self, path: &str) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in self.pixels.chunks_exact(self.width as usize).rev() {
+            for c in row {
+                for value in &[c.x, c.y, c.z] {
+                    file.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        file.flush()?;
+        Ok(())
+    }
+    /// Quantizes the pixels after `encode` has mapped them to [0, 1].
+    fn to_rgb8(&self, encode: impl Fn(Color) -> Color) -> RgbImage {
+        let quantize = |v: f32| (v * 255.999) as u8;
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let c = encode(self.pixels[(y * self.width + x) as usize]);
+            Rgb([quantize(c.x), quantize(c.y), quantize(c.z)])
+        })
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -78,9 +201,50 @@ mod tests {
     fn test_image_save() -> Result<()> {
         let mut image = Image::new(100, 100);
         image.set_pixel(50, 50, Color::new(1.0, 0.0, 0.0), 1);
-        image.save("test.png")?;
+        image.save("test.png", &Display::default())?;
         std::fs::remove_file("test.png")
             .with_context(|| "Should never fail unless the previous line failed")?;
         Ok(())
     }
+
+    #[test]
+    fn test_image_keeps_dynamic_range() {
+        let mut image = Image::new(4, 2);
+        image.set_pixel(1, 0, Color::new(40.0, 0.5, -2.0), 4);
+        assert_eq!(image.get_pixel(1, 0), Color::new(10.0, 0.125, -0.5));
+    }
+
+    #[test]
+    fn test_image_save_pfm() -> Result<()> {
+        let mut image = Image::new(2, 2);
+        image.set_pixel(1, 0, Color::new(3.0, 2.0, 1.0), 1);
+        let path = std::env::temp_dir().join("test_image_save_pfm.pfm");
+        image.save(path.to_str().unwrap(), &Display::default())?;
+        let bytes = std::fs::read(&path)?;
+        std::fs::remove_file(&path)?;
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        // The bottom row comes first, so (1, 0) is the second pixel.
+        let red = &bytes[header.len() + 12..header.len() + 16];
+        assert_eq!(f32::from_le_bytes([red[0], red[1], red[2], red[3]]), 3.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_image_save_exr_with_layers() -> Result<()> {
+        let image = Image::new(2, 2);
+        let depth = Image::new(2, 2);
+        let path = std::env::temp_dir().join("test_image_save_exr_with_layers.exr");
+        image.save_exr_with_layers(path.to_str().unwrap(), &[("depth", &["Z"], &depth)])?;
+        let meta = exr::meta::MetaData::read_from_file(&path, false)?;
+        std::fs::remove_file(&path)?;
+        let names = meta.headers[0]
+            .channels
+            .list
+            .iter()
+            .map(|c| c.name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["B", "G", "R", "depth.Z"]);
+        Ok(())
+    }
 }
@@ -0,0 +1,149 @@
+use std::fmt::Debug;
+
+use crate::{
+    hittable::HitRecord,
+    hittablelist::HittableList,
+    ray::Ray,
+    sampler::Sampler,
+    vectors::{Color, Vec3},
+};
+
+pub mod ao;
+pub mod debug;
+pub mod naive;
+pub mod path;
+pub mod whitted;
+
+/// Computes the radiance arriving along camera rays.
+#[typetag::serde(tag = "type")]
+pub trait Integrator: Debug + IntegratorClone + Send + Sync {
+    fn ray_color(&self, r: Ray, world: &HittableList, sampler: &mut dyn Sampler) -> Color;
+    /// Overrides the path length limits: at most `max_depth` rays per path, with Russian
+    /// roulette allowed to end paths after `rr_depth` of them. Integrators that do not
+    /// trace paths ignore this.
+    fn set_depth(&mut self, _max_depth: Option<u32>, _rr_depth: Option<u32>) {}
+}
+pub trait IntegratorClone {
+    fn clone_box(&self) -> Box<dyn Integrator>;
+}
+impl<T> IntegratorClone for T
+where
+    T: 'static + Integrator + Clone,
+{
+    fn clone_box(&self) -> Box<dyn Integrator> {
+        Box::new(self.clone())
+    }
+}
+
+/// Parses an integrator given on the command line, either as a bare type name such as
+/// `ao` or as inline YAML such as `{type: ao, distance: 2}`.
+pub fn parse(s: &str) -> anyhow::Result<Box<dyn Integrator>> {
+    let yaml = if s.contains(':') {
+        s.to_string()
+    } else {
+        format!("type: {}", s)
+    };
+    Ok(serde_yaml::from_str(&yaml)?)
+}
+
+/// Direct lighting at `rec` from one randomly picked light and from the background. With
+/// `mis` the samples are weighted for combination with BSDF sampling, otherwise they are
+/// the only estimate of direct lighting.
+fn direct_lighting(
+    r: Ray,
+    rec: &HitRecord,
+    world: &HittableList,
+    mis: bool,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let weight = |light_pdf: f32, bsdf_pdf: f32| {
+        if mis {
+            power_heuristic(light_pdf, bsdf_pdf) / light_pdf
+        } else {
+            1.0 / light_pdf
+        }
+    };
+    let mut result = Color::new_all(0.0);
+    let u = sampler.get_1d();
+    let (u1, u2) = sampler.get_2d();
+    if let Some((light, direction, light_pdf)) = world.sample_light(rec.p, u, u1, u2) {
+        if let Some((f, bsdf_pdf)) = rec.mat.bsdf(r, rec, direction) {
+            // The light is visible if the shadow ray reaches it before anything else.
+            if f != Color::new_all(0.0) {
+                if let Some((object, light_rec)) =
+                    world.hit_object(Ray::new(rec.p, direction), 0.001, f32::MAX)
+                {
+                    if object == light {
+                        result = result
+                            + f * light_rec.mat.emitted(&light_rec)
+                                * Vec3::new_all(weight(light_pdf, bsdf_pdf));
+                    }
+                }
+            }
+        }
+    }
+    let (u1, u2) = sampler.get_2d();
+    if let Some((direction, radiance, light_pdf)) = world.background.sample(u1, u2) {
+        if let Some((f, bsdf_pdf)) = rec.mat.bsdf(r, rec, direction) {
+            if f != Color::new_all(0.0)
+                && !world.occluded(Ray::new(rec.p, direction), 0.001, f32::MAX)
+            {
+                result = result + f * radiance * Vec3::new_all(weight(light_pdf, bsdf_pdf));
+            }
+        }
+    }
+    result
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+/// Randomly ends a path whose `throughput` has become small, returning the factor to scale
+/// the throughput of surviving paths by to keep the estimate unbiased, or `None` if the
+/// path ends. Paths are never ended before `rr_depth` rays.
+fn russian_roulette(
+    throughput: Color,
+    depth: u32,
+    rr_depth: u32,
+    sampler: &mut dyn Sampler,
+) -> Option<f32> {
+    // Drawn even when unused, so later bounces see the same sample dimensions.
+    let u = sampler.get_1d();
+    if depth < rr_depth {
+        return Some(1.0);
+    }
+    let survival = throughput.max_component().min(0.95);
+    if survival <= 0.0 || u >= survival {
+        return None;
+    }
+    Some(1.0 / survival)
+}
+
+fn default_max_depth() -> u32 {
+    50
+}
+fn default_rr_depth() -> u32 {
+    5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_integrator() {
+        let integrator = parse("ao").unwrap();
+        assert_eq!(
+            format!("{:?}", integrator),
+            format!("{:?}", ao::AmbientOcclusion::default())
+        );
+        let integrator = parse("{type: ao, distance: 2}").unwrap();
+        assert!(format!("{:?}", integrator).contains("distance: 2.0"));
+        assert!(parse("bogus").is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hittable::HitRecord,
+    hittablelist::HittableList,
+    integrator::Integrator,
+    ray::Ray,
+    sampler::Sampler,
+    vectors::{Color, Vec3},
+};
+
+/// Ambient occlusion: the fraction of cosine-weighted directions around the first hit that
+/// escape within `distance`. Rays that miss everything are white.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AmbientOcclusion {
+    /// Occluders further away than this are ignored
+    #[serde(default = "default_distance")]
+    pub distance: f32,
+    /// Occlusion rays per camera ray
+    #[serde(default = "default_samples")]
+    pub samples: u32,
+}
+fn default_distance() -> f32 {
+    f32::INFINITY
+}
+fn default_samples() -> u32 {
+    1
+}
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        AmbientOcclusion {
+            distance: default_distance(),
+            samples: default_samples(),
+        }
+    }
+}
+#[typetag::serde(name = "ao")]
+impl Integrator for AmbientOcclusion {
+    fn ray_color(&self, r: Ray, world: &HittableList, sampler: &mut dyn Sampler) -> Color {
+        let mut rec = HitRecord::empty();
+        if !world.hit(r, 0.001, f32::MAX, &mut rec) {
+            return Color::new_all(1.0);
+        }
+        let unoccluded = (0..self.samples)
+            .filter(|_| {
+                let (u1, u2) = sampler.get_2d();
+                let mut direction = rec.normal + Vec3::unit_vector_from(u1, u2);
+                if direction.near_zero() {
+                    direction = rec.normal;
+                }
+                let t_max = self.distance / direction.length();
+                !world.occluded(Ray::new(rec.p, direction), 0.001, t_max)
+            })
+            .count();
+        Color::new_all(unoccluded as f32 / self.samples.max(1) as f32)
+    }
+}
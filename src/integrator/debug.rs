@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hittable::HitRecord, hittablelist::HittableList, integrator::Integrator, ray::Ray,
+    sampler::Sampler, vectors::Color,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DebugChannel {
+    /// Shading normal facing the camera, mapped from [-1, 1] to [0, 1]
+    Normal,
+    /// Base color of the material
+    Albedo,
+}
+/// Shows a property of the first surface hit, without any lighting.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DebugIntegrator {
+    #[serde(default = "default_channel")]
+    pub channel: DebugChannel,
+}
+fn default_channel() -> DebugChannel {
+    DebugChannel::Normal
+}
+impl Default for DebugIntegrator {
+    fn default() -> Self {
+        DebugIntegrator {
+            channel: default_channel(),
+        }
+    }
+}
+#[typetag::serde(name = "debug")]
+impl Integrator for DebugIntegrator {
+    fn ray_color(&self, r: Ray, world: &HittableList, _sampler: &mut dyn Sampler) -> Color {
+        let mut rec = HitRecord::empty();
+        if !world.hit(r, 0.001, f32::MAX, &mut rec) {
+            return Color::new_all(0.0);
+        }
+        match self.channel {
+            DebugChannel::Normal => (rec.normal + Color::new_all(1.0)) * Color::new_all(0.5),
+            DebugChannel::Albedo => rec.mat.albedo(&rec),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hittablelist::HittableList,
+    integrator::{default_max_depth, default_rr_depth, russian_roulette, Integrator},
+    ray::Ray,
+    sampler::Sampler,
+    vectors::{Color, Vec3},
+};
+
+/// Path tracer that only follows the directions picked by the materials, so lights are
+/// found by chance. Slow to converge, but a simple reference for the other integrators.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NaivePathTracer {
+    #[serde(default = "default_max_depth")]
+    pub max_depth: u32,
+    #[serde(default = "default_rr_depth")]
+    pub rr_depth: u32,
+}
+impl Default for NaivePathTracer {
+    fn default() -> Self {
+        NaivePathTracer {
+            max_depth: default_max_depth(),
+            rr_depth: default_rr_depth(),
+        }
+    }
+}
+#[typetag::serde(name = "naive")]
+impl Integrator for NaivePathTracer {
+    fn ray_color(&self, r: Ray, world: &HittableList, sampler: &mut dyn Sampler) -> Color {
+        let mut radiance = Color::new_all(0.0);
+        let mut throughput = Color::new_all(1.0);
+        let mut ray = r;
+        for depth in 0..self.max_depth {
+            let (_, rec) = match world.hit_object(ray, 0.001, f32::MAX) {
+                Some(hit) => hit,
+                None => {
+                    radiance = radiance + throughput * world.background.value(ray);
+                    break;
+                }
+            };
+            radiance = radiance + throughput * rec.mat.emitted(&rec);
+            let (scattered, attenuation) = match rec.mat.scatter(ray, &rec, sampler) {
+                Some(scatter) => scatter,
+                None => break,
+            };
+            throughput = throughput * attenuation;
+            match russian_roulette(throughput, depth + 1, self.rr_depth, sampler) {
+                Some(scale) => throughput = throughput * Vec3::new_all(scale),
+                None => break,
+            }
+            ray = scattered;
+        }
+        radiance
+    }
+    fn set_depth(&mut self, max_depth: Option<u32>, rr_depth: Option<u32>) {
+        self.max_depth = max_depth.unwrap_or(self.max_depth);
+        self.rr_depth = rr_depth.unwrap_or(self.rr_depth);
+    }
+}
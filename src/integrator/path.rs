@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hittablelist::HittableList,
+    integrator::{
+        default_max_depth, default_rr_depth, direct_lighting, power_heuristic, russian_roulette,
+        Integrator,
+    },
+    ray::Ray,
+    sampler::Sampler,
+    vectors::{Color, Vec3},
+};
+
+/// Path tracer that samples lights and the background directly at every diffuse hit and
+/// combines this with BSDF sampling through multiple importance sampling.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PathTracer {
+    #[serde(default = "default_max_depth")]
+    pub max_depth: u32,
+    #[serde(default = "default_rr_depth")]
+    pub rr_depth: u32,
+}
+impl Default for PathTracer {
+    fn default() -> Self {
+        PathTracer {
+            max_depth: default_max_depth(),
+            rr_depth: default_rr_depth(),
+        }
+    }
+}
+#[typetag::serde(name = "path")]
+impl Integrator for PathTracer {
+    fn ray_color(&self, r: Ray, world: &HittableList, sampler: &mut dyn Sampler) -> Color {
+        let mut radiance = Color::new_all(0.0);
+        let mut throughput = Color::new_all(1.0);
+        let mut ray = r;
+        // Pdf with which `ray` was sampled from a non-specular surface, whose direct lighting
+        // was then also sampled explicitly. `None` for camera rays and specular bounces.
+        let mut bsdf_pdf = None;
+        for depth in 0..self.max_depth {
+            let (object, rec) = match world.hit_object(ray, 0.001, f32::MAX) {
+                Some(hit) => hit,
+                None => {
+                    let mut background = world.background.value(ray);
+                    if let Some(pdf) = bsdf_pdf {
+                        let light_pdf = world.background.pdf(ray.direction);
+                        background = background * Vec3::new_all(power_heuristic(pdf, light_pdf));
+                    }
+                    radiance = radiance + throughput * background;
+                    break;
+                }
+            };
+            let mut emitted = rec.mat.emitted(&rec);
+            if let Some(pdf) = bsdf_pdf {
+                let light_pdf = world.light_pdf(object, ray.origin, ray.direction);
+                emitted = emitted * Vec3::new_all(power_heuristic(pdf, light_pdf));
+            }
+            radiance = radiance + throughput * emitted;
+
+            let (scattered, attenuation) = match rec.mat.scatter(ray, &rec, sampler) {
+                Some(scatter) => scatter,
+                None => break,
+            };
+            bsdf_pdf = None;
+            if let Some((_, pdf)) = rec.mat.bsdf(ray, &rec, scattered.direction) {
+                radiance = radiance + throughput * direct_lighting(ray, &rec, world, true, sampler);
+                bsdf_pdf = Some(pdf);
+            }
+            throughput = throughput * attenuation;
+            match russian_roulette(throughput, depth + 1, self.rr_depth, sampler) {
+                Some(scale) => throughput = throughput * Vec3::new_all(scale),
+                None => break,
+            }
+            ray = scattered;
+        }
+        radiance
+    }
+    fn set_depth(&mut self, max_depth: Option<u32>, rr_depth: Option<u32>) {
+        self.max_depth = max_depth.unwrap_or(self.max_depth);
+        self.rr_depth = rr_depth.unwrap_or(self.rr_depth);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        background::Background, material::Diffuse, sampler::IndependentSampler,
+        shapes::sphere::Sphere,
+    };
+
+    #[test]
+    fn test_russian_roulette_is_unbiased() {
+        // A convex diffuse object under a uniform sky reflects exactly its albedo.
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(
+            Vec3::new_all(0.0),
+            1.0,
+            Box::new(Diffuse::new(Color::new_all(0.5))),
+        )));
+        world.background = Background::Solid {
+            color: Color::new_all(1.0),
+        };
+        let integrator = PathTracer {
+            max_depth: 50,
+            rr_depth: 0,
+        };
+        let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let n = 20000;
+        let mut sampler = IndependentSampler::new(0);
+        let mean = (0..n)
+            .map(|i| {
+                sampler.start_pixel_sample(0, 0, i);
+                integrator.ray_color(r, &world, &mut sampler).x
+            })
+            .sum::<f32>()
+            / n as f32;
+        assert!((mean - 0.5).abs() < 0.02, "mean {}", mean);
+    }
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hittable::HitRecord,
+    hittablelist::HittableList,
+    integrator::{direct_lighting, Integrator},
+    ray::Ray,
+    sampler::Sampler,
+    vectors::Color,
+};
+
+/// Whitted-style ray tracer for quick previews: diffuse surfaces only get direct lighting,
+/// while mirrors and glass are followed recursively. Skies that cannot be sampled for direct
+/// lighting instead add an unshadowed ambient term, from the sky color above the surface.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Whitted {
+    #[serde(default = "default_max_depth")]
+    pub max_depth: u32,
+}
+fn default_max_depth() -> u32 {
+    5
+}
+impl Default for Whitted {
+    fn default() -> Self {
+        Whitted {
+            max_depth: default_max_depth(),
+        }
+    }
+}
+impl Whitted {
+    fn trace(&self, r: Ray, world: &HittableList, sampler: &mut dyn Sampler, depth: u32) -> Color {
+        let mut rec = HitRecord::empty();
+        if depth == 0 {
+            return Color::new_all(0.0);
+        }
+        if !world.hit(r, 0.001, f32::MAX, &mut rec) {
+            return world.background.value(r);
+        }
+        let emitted = rec.mat.emitted(&rec);
+        match rec.mat.scatter(r, &rec, sampler) {
+            Some((scattered, attenuation)) => {
+                if rec.mat.bsdf(r, &rec, scattered.direction).is_some() {
+                    emitted
+                        + direct_lighting(r, &rec, world, false, sampler)
+                        + self.ambient(&rec, world)
+                } else {
+                    emitted + attenuation * self.trace(scattered, world, sampler, depth - 1)
+                }
+            }
+            None => emitted,
+        }
+    }
+    fn ambient(&self, rec: &HitRecord, world: &HittableList) -> Color {
+        if world.background.is_sampled() {
+            return Color::new_all(0.0);
+        }
+        rec.mat.albedo(rec) * world.background.value(Ray::new(rec.p, rec.normal))
+    }
+}
+#[typetag::serde(name = "whitted")]
+impl Integrator for Whitted {
+    fn ray_color(&self, r: Ray, world: &HittableList, sampler: &mut dyn Sampler) -> Color {
+        self.trace(r, world, sampler, self.max_depth)
+    }
+    fn set_depth(&mut self, max_depth: Option<u32>, _rr_depth: Option<u32>) {
+        self.max_depth = max_depth.unwrap_or(self.max_depth);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        background::Background, material::Diffuse, sampler::IndependentSampler,
+        shapes::sphere::Sphere, vectors::Vec3,
+    };
+
+    #[test]
+    fn test_diffuse_under_gradient_sky() {
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(
+            Vec3::new_all(0.0),
+            1.0,
+            Box::new(Diffuse::new(Color::new_all(0.5))),
+        )));
+        world.background = Background::Gradient {
+            bottom: Color::new_all(1.0),
+            top: Color::new(0.5, 0.7, 1.0),
+        };
+        let mut sampler = IndependentSampler::new(0);
+        sampler.start_pixel_sample(0, 0, 0);
+        let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let color = Whitted::default().ray_color(r, &world, &mut sampler);
+        // The front of the sphere faces the horizon, halfway between the two colors.
+        assert!(
+            (color - Color::new(0.375, 0.425, 0.5)).length() < 1e-4,
+            "{:?}",
+            color
+        );
+    }
+}
@@ -1,35 +1,55 @@
 #![allow(dead_code)]
+// typetag 0.1 registers impls from inside anonymous consts
+#![allow(non_local_definitions)]
+mod aabb;
+mod adaptive;
+mod aov;
+mod background;
+mod bvh;
 mod camera;
+mod checkpoint;
+mod denoise;
+mod environment;
+mod gltf_import;
 mod hittable;
 mod hittablelist;
 mod image;
+mod integrator;
 mod material;
 mod ray;
+mod sampler;
 mod shapes;
+mod texture;
+mod tiles;
+mod tonemap;
 mod vectors;
 
-use std::{
-    fs::read,
-    sync::{Arc, Mutex},
-};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::vectors::*;
+use adaptive::SamplingPlan;
 use anyhow::Result;
+use aov::Aov;
+use bvh::SplitMethod;
 use camera::Camera;
-use hittable::HitRecord;
+use checkpoint::{Header, RenderState};
+use denoise::Denoiser;
 use hittablelist::HittableList;
 use indicatif::{ProgressBar, ProgressStyle};
-use rand::Rng;
-use ray::Ray;
-use std::path::PathBuf;
+use integrator::Integrator;
+use rayon::prelude::*;
+use sampler::{Sampler, SamplerKind};
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
+use tiles::{Tile, TileOrder};
+use tonemap::ToneMapper;
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "raytracer",
     about = "A raytracer based on Ray Tracing in One Weekend"
 )]
 struct Opt {
-    /// Output file
+    /// Output file. .exr, .hdr and .pfm keep the linear radiance, other formats are tone mapped
     #[structopt(parse(from_os_str))]
     output: PathBuf,
     /// World file
@@ -42,13 +62,104 @@ struct Opt {
     #[structopt(short, long, default_value = "450")]
     height: u32,
 
-    /// Number of samples per pixel
+    /// Number of samples per pixel, or the most any pixel gets with --noise-threshold
     #[structopt(short, long, default_value = "100")]
     samples: u32,
 
+    /// Stop sampling pixels once their relative noise estimate falls below this
+    #[structopt(long)]
+    noise_threshold: Option<f32>,
+
+    /// Samples per pixel before the noise is first estimated with --noise-threshold, and
+    /// between later estimates
+    #[structopt(long, default_value = "16")]
+    min_samples: u32,
+
+    /// Write an image of the samples each pixel got
+    #[structopt(long, parse(from_os_str))]
+    spp_heatmap: Option<PathBuf>,
+
+    /// Width and height of the tiles the image is split into for rendering
+    #[structopt(long, default_value = "16")]
+    tile_size: u32,
+
+    /// Order in which tiles are rendered: scanline, spiral or hilbert
+    #[structopt(long, default_value = "hilbert")]
+    tile_order: TileOrder,
+
     /// Number of threads to use [default: number of cores]
     #[structopt(short, long)]
     threads: Option<u32>,
+
+    /// How to split BVH nodes: sah or midpoint
+    #[structopt(long, default_value = "sah")]
+    bvh_split: SplitMethod,
+
+    /// Exposure in stops, overriding the scene's display settings
+    #[structopt(long, allow_hyphen_values = true)]
+    exposure: Option<f32>,
+
+    /// Tone mapper for 8-bit output: clamp, reinhard, extended_reinhard, aces or agx,
+    /// overriding the scene's display settings
+    #[structopt(long)]
+    tonemap: Option<ToneMapper>,
+
+    /// Rendering algorithm, overriding the scene's: path, naive, ao, debug or whitted, or
+    /// inline YAML such as "{type: ao, distance: 2}"
+    #[structopt(long, parse(try_from_str = integrator::parse))]
+    integrator: Option<Box<dyn Integrator>>,
+
+    /// Maximum number of rays per path, overriding the integrator's
+    #[structopt(long)]
+    max_depth: Option<u32>,
+
+    /// Number of rays per path before Russian roulette may end it, overriding the
+    /// integrator's
+    #[structopt(long)]
+    rr_depth: Option<u32>,
+
+    /// Random number generator for pixel samples, overriding the scene's: independent,
+    /// stratified, halton, sobol or blue_noise
+    #[structopt(long)]
+    sampler: Option<SamplerKind>,
+
+    /// Seed for all random numbers. The same seed gives the same image whatever the number
+    /// of threads
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+
+    /// Samples per pixel in each progressive pass
+    #[structopt(long, default_value = "16")]
+    pass_samples: u32,
+
+    /// Periodically save the render state here, to continue it later with --resume
+    #[structopt(long, parse(from_os_str))]
+    checkpoint: Option<PathBuf>,
+
+    /// Seconds between checkpoints. A checkpoint is always saved when the render finishes
+    #[structopt(long, default_value = "60")]
+    checkpoint_interval: u64,
+
+    /// Continue the render saved in this checkpoint, which is also where new checkpoints go
+    /// unless --checkpoint is given. Raise --samples to add samples to a finished render.
+    /// The seed and sampler are taken from the checkpoint, and the stratified sampler keeps
+    /// the strata of the checkpoint's sample count
+    #[structopt(long, parse(from_os_str))]
+    resume: Option<PathBuf>,
+
+    /// Save the image so far to the output file every this many passes
+    #[structopt(long)]
+    preview_every: Option<u32>,
+
+    /// Extra pass to write: depth, normal, albedo, object_id, material_id or position. May
+    /// be repeated. EXR output gets them as extra channels, other formats as separate files
+    /// named like output.depth.png
+    #[structopt(long = "aov", number_of_values = 1)]
+    aovs: Vec<Aov>,
+
+    /// Denoise the image, guided by the normals and albedo of the first hits
+    #[structopt(long)]
+    denoise: bool,
 }
 
 fn main() -> Result<()> {
@@ -62,19 +173,78 @@ fn main() -> Result<()> {
     // Image
     let image_width = opt.width;
     let image_height = opt.height;
-    if (image_width % 2) != 0 {
-        panic!("image width must be even");
-    }
-    if image_height % 2 != 0 {
-        panic!("image height must be even");
-    }
     let samples_per_pixel = opt.samples;
-    let max_depth = 50;
-    let block_width = image_width / highest_power_of_2(image_width);
-    let block_height = image_height / highest_power_of_2(image_height);
+    let plan = SamplingPlan {
+        min_samples: opt.min_samples,
+        max_samples: samples_per_pixel,
+        noise_threshold: opt.noise_threshold,
+    };
 
     // World
-    let world = serde_yaml::from_slice::<HittableList>(&read(opt.world)?)?;
+    let mut world = HittableList::load(&opt.world)?;
+    let now = std::time::Instant::now();
+    let node_count = world.build_bvh(opt.bvh_split);
+    println!(
+        "Built BVH over {} objects with {} nodes in {:.2}ms",
+        world.objects.len(),
+        node_count,
+        now.elapsed().as_secs_f64() * 1000.0
+    );
+    if let Some(integrator) = opt.integrator {
+        world.integrator = integrator;
+    }
+    world.integrator.set_depth(opt.max_depth, opt.rr_depth);
+    let mut saved_aovs = world.aovs.clone();
+    for aov in &opt.aovs {
+        if !saved_aovs.contains(aov) {
+            saved_aovs.push(*aov);
+        }
+    }
+    // The denoiser's feature buffers are rendered like AOVs, but only saved if requested.
+    let mut aovs = saved_aovs.clone();
+    if opt.denoise {
+        for aov in &[Aov::Normal, Aov::Albedo] {
+            if !aovs.contains(aov) {
+                aovs.push(*aov);
+            }
+        }
+    }
+    let pass_samples = opt.pass_samples.max(1);
+    let header = Header {
+        width: image_width,
+        height: image_height,
+        seed: opt.seed,
+        sampler: opt.sampler.unwrap_or(world.sampler),
+        sampler_samples: samples_per_pixel,
+        scene: checkpoint::fingerprint(&std::fs::read(&opt.world)?),
+        settings: checkpoint::fingerprint(
+            format!(
+                "{}pass_samples: {}\nmin_samples: {}\n",
+                serde_yaml::to_string(&world.integrator)?,
+                pass_samples,
+                opt.min_samples
+            )
+            .as_bytes(),
+        ),
+        aovs: aovs.clone(),
+        passes: 0,
+    };
+    let mut state = match &opt.resume {
+        Some(path) => {
+            let state = RenderState::load(path)?;
+            state.check_compatible(&header)?;
+            println!(
+                "Resuming {} after {} passes",
+                path.display(),
+                state.header.passes
+            );
+            aovs = state.header.aovs.clone();
+            state
+        }
+        None => RenderState::new(header),
+    };
+    let light_count = world.collect_lights();
+    println!("Found {} lights for direct lighting", light_count);
 
     // Camera
     let camera = Camera::new(
@@ -83,97 +253,244 @@ fn main() -> Result<()> {
         Vec3::new(0.0, 1.0, 0.0),
         world.camera_fov,
         image_width as f32 / image_height as f32,
-    );
+        world.camera_aperture,
+        world.focus_distance(),
+    )
+    .with_projection(world.camera_projection);
+
+    // Display
+    let mut display = world.display;
+    if let Some(exposure) = opt.exposure {
+        display.exposure = exposure;
+    }
+    if let Some(tonemap) = opt.tonemap {
+        display.tonemap = tonemap;
+    }
     println!(
-        r"Rendering to file {} at resolution {}x{} with {} samples and max recurse {}
-With {}x{} blocks",
+        r"Rendering to file {} at resolution {}x{} with {} samples using {:?}
+and the {:?} sampler with seed {}, in {}x{} tiles in {:?} order",
         opt.output.to_str().unwrap(),
         image_width,
         image_height,
         samples_per_pixel,
-        max_depth,
-        block_width,
-        block_height
+        world.integrator,
+        state.header.sampler,
+        state.header.seed,
+        opt.tile_size,
+        opt.tile_size,
+        opt.tile_order
     );
-    // Progress bar
-    let bar = Arc::new(Mutex::new(
-        ProgressBar::new(image_width as u64 * image_height as u64).with_style(
-            ProgressStyle::default_bar().template(
-                "{bar:40} [{per_sec} pixels per second] [{elapsed_precise} elapsed] [{eta_precise} left]",
-            ),
-        ),
-    ));
-    // Render
+    // Progress bar, counting samples
+    let bar =
+        ProgressBar::new(image_width as u64 * image_height as u64 * samples_per_pixel as u64)
+            .with_style(ProgressStyle::default_bar().template(
+                "{bar:40} [{per_sec} samples per second] [{elapsed_precise} elapsed] [{eta_precise} left]",
+            ));
+    bar.set_position(
+        state
+            .pixels
+            .iter()
+            .map(|p| p.estimate.count.min(samples_per_pixel) as u64)
+            .sum(),
+    );
+    // Render in passes of at most `pass_samples` samples per pixel, until every pixel is done.
     let now = std::time::Instant::now();
-    let image = Arc::new(Mutex::new(image::Image::new(image_width, image_height)));
-    let image_clone = image.clone();
-
-    rayon::scope(|s| {
-        let world_clone = Arc::new(world);
-        for block_x in 0..(image_width / block_width) {
-            for block_y in 0..(image_height / block_height) {
-                let image_clone = image_clone.clone();
-                let bar_clone = bar.clone();
-                let world_clone = world_clone.clone();
-                s.spawn(move |_| {
-                    for x in block_x * block_width..(block_x + 1) * block_width {
-                        for y in block_y * block_height..(block_y + 1) * block_height {
-                            let mut pixel_color = Vec3::new(0.0, 0.0, 0.0);
-                            for _s in 0..samples_per_pixel {
-                                let u = (x as f32 + rand::thread_rng().gen::<f32>())
-                                    / image_width as f32;
-                                let v = (y as f32 + rand::thread_rng().gen::<f32>())
-                                    / image_height as f32;
-                                let r = camera.get_ray(u, v);
-                                pixel_color = pixel_color + ray_color(r, &world_clone, max_depth);
+    let checkpoint = opt.checkpoint.clone().or(opt.resume.clone());
+    let checkpoint_interval = std::time::Duration::from_secs(opt.checkpoint_interval);
+    let mut last_checkpoint = std::time::Instant::now();
+    let (seed, sampler_kind, sampler_samples) = (
+        state.header.seed,
+        state.header.sampler,
+        state.header.sampler_samples,
+    );
+    let tiles = tiles::tiles(image_width, image_height, opt.tile_size, opt.tile_order);
+    // Adds up to `pass_samples` samples to the pixels of `tile`, returning their new state
+    // row by row.
+    let render_tile = |tile: &Tile, state: &RenderState, sampler: &mut dyn Sampler| {
+        let mut pixels = Vec::with_capacity((tile.width * tile.height) as usize);
+        let mut samples = 0;
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                let mut pixel = state.pixels[state.index(x, y)].clone();
+                let mut budget = pass_samples;
+                loop {
+                    let batch = plan.next_batch(&pixel.estimate).min(budget);
+                    if batch == 0 {
+                        break;
+                    }
+                    budget -= batch;
+                    samples += batch as u64;
+                    for _ in 0..batch {
+                        sampler.start_pixel_sample(x, y, pixel.estimate.count);
+                        let (jitter_x, jitter_y) = sampler.get_2d();
+                        let u = (x as f32 + jitter_x) / image_width as f32;
+                        let v = (y as f32 + jitter_y) / image_height as f32;
+                        let r = match camera.get_ray(u, v, sampler) {
+                            Some(r) => r,
+                            None => {
+                                pixel.estimate.add(Color::new_all(0.0));
+                                continue;
+                            }
+                        };
+                        if !aovs.is_empty() {
+                            let first_sample = pixel.estimate.count == 0;
+                            let values = Aov::evaluate(&aovs, first_sample, r, &world, &camera);
+                            for (i, aov) in aovs.iter().enumerate() {
+                                if aov.is_averaged() {
+                                    pixel.aovs[i] = pixel.aovs[i] + values[i];
+                                } else if first_sample {
+                                    pixel.aovs[i] = values[i];
+                                }
                             }
-
-                            image_clone.lock().unwrap().set_pixel(
-                                x,
-                                y,
-                                pixel_color,
-                                samples_per_pixel,
-                            );
                         }
-                        bar_clone.lock().unwrap().inc(16);
+                        pixel
+                            .estimate
+                            .add(world.integrator.ray_color(r, &world, sampler));
                     }
-                });
+                }
+                pixels.push(pixel);
+            }
+        }
+        bar.inc(samples);
+        pixels
+    };
+    loop {
+        if !state
+            .pixels
+            .iter()
+            .any(|p| plan.next_batch(&p.estimate) > 0)
+        {
+            break;
+        }
+        // Each thread takes the next tile in order until none are left, and renders it into
+        // its own buffer. The buffers are copied into the state once the pass is done.
+        let next_tile = AtomicUsize::new(0);
+        let rendered = (0..rayon::current_num_threads())
+            .into_par_iter()
+            .flat_map_iter(|_| {
+                let mut sampler = sampler_kind.create(seed, sampler_samples);
+                let mut rendered = Vec::new();
+                while let Some(tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                    rendered.push((*tile, render_tile(tile, &state, sampler.as_mut())));
+                }
+                rendered
+            })
+            .collect::<Vec<_>>();
+        for (tile, pixels) in rendered {
+            for (row, y) in (tile.y..tile.y + tile.height).enumerate() {
+                let start = state.index(tile.x, y);
+                let tile_row = row * tile.width as usize..(row + 1) * tile.width as usize;
+                state.pixels[start..start + tile.width as usize]
+                    .clone_from_slice(&pixels[tile_row]);
+            }
+        }
+        state.header.passes += 1;
+        if let Some(every) = opt.preview_every {
+            if state.header.passes % every.max(1) == 0 {
+                state.image().save(opt.output.to_str().unwrap(), &display)?;
             }
         }
-    });
+        if let Some(path) = &checkpoint {
+            if last_checkpoint.elapsed() >= checkpoint_interval {
+                state.save(path)?;
+                last_checkpoint = std::time::Instant::now();
+            }
+        }
+    }
     // Finish
-    bar.lock().unwrap().finish();
+    bar.finish();
     let elapsed = now.elapsed();
     println!("Took {:.2}s", elapsed.as_secs_f64());
+    if let Some(path) = &checkpoint {
+        state.save(path)?;
+        println!(
+            "Saved checkpoint after {} passes to {}",
+            state.header.passes,
+            path.display()
+        );
+    }
+    {
+        let sample_counts = state.sample_counts();
+        if plan.noise_threshold.is_some() {
+            let total = sample_counts
+                .pixels()
+                .iter()
+                .map(|c| c.x as f64)
+                .sum::<f64>();
+            println!(
+                "Averaged {:.1} samples per pixel",
+                total / (image_width * image_height) as f64
+            );
+        }
+        if let Some(path) = &opt.spp_heatmap {
+            let path = path.to_str().unwrap();
+            if is_float_format(path) {
+                sample_counts.save_data(path)?;
+            } else {
+                adaptive::heatmap(&sample_counts, samples_per_pixel).save_data(path)?;
+            }
+        }
+    }
     {
-        image
-            .lock()
-            .unwrap()
-            .save(opt.output.to_str().unwrap())
-            .expect("Failed to save image");
+        let mut image = state.image();
+        let aov_image = |aov: &Aov| state.aov_image(aovs.iter().position(|a| a == aov).unwrap());
+        if opt.denoise {
+            let now = std::time::Instant::now();
+            image = Denoiser::default().denoise(
+                &image,
+                &aov_image(&Aov::Normal),
+                &aov_image(&Aov::Albedo),
+            );
+            println!("Denoised in {:.2}s", now.elapsed().as_secs_f64());
+        }
+        let aov_images = saved_aovs.iter().map(aov_image).collect::<Vec<_>>();
+        let output = opt.output.to_str().unwrap();
+        let is_exr = opt
+            .output
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("exr"));
+        if is_exr && !saved_aovs.is_empty() {
+            let layers = saved_aovs
+                .iter()
+                .zip(aov_images.iter())
+                .map(|(aov, image)| (aov.name(), aov.channels(), image))
+                .collect::<Vec<_>>();
+            image.save_exr_with_layers(output, &layers)?;
+        } else {
+            image.save(output, &display)?;
+            for (aov, aov_image) in saved_aovs.iter().zip(aov_images.iter()) {
+                let path = aov_path(&opt.output, aov);
+                if is_float_format(&path) {
+                    aov_image.save_data(&path)?;
+                } else {
+                    aov.for_display(aov_image).save_data(&path)?;
+                }
+            }
+        }
         println!("Saved image");
     }
 
     Ok(())
 }
-fn ray_color(r: Ray, world: &HittableList, depth: u32) -> Color {
-    let mut rec = HitRecord::empty();
-    if depth <= 0 {
-        return Color::new_all(0.0);
-    }
-
-    if world.hit(r, 0.001, f32::MAX, &mut rec) {
-        if let Some((scattered, attenuation)) = rec.mat.scatter(r, &rec) {
-            return attenuation * ray_color(scattered, world, depth - 1);
-        }
-        return Color::new_all(0.0);
-    }
-    let unit_direction = r.direction.normalize();
-    let t = 0.5 * (unit_direction.y + 1.0);
-    return Vec3::new_all(1.0 - t) * Color::new(1.0, 1.0, 1.0)
-        + Vec3::new_all(t) * Color::new(0.5, 0.7, 1.0);
+/// `output` with the name of `aov` inserted before the extension.
+fn aov_path(output: &Path, aov: &Aov) -> String {
+    let stem = output
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("output");
+    let name = match output.extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!("{}.{}.{}", stem, aov.name(), extension),
+        None => format!("{}.{}", stem, aov.name()),
+    };
+    output.with_file_name(name).to_str().unwrap().to_string()
 }
-
-fn highest_power_of_2(n: u32) -> u32 {
-    return n & (!(n - 1));
+fn is_float_format(path: &str) -> bool {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    matches!(
+        extension.as_deref(),
+        Some("exr") | Some("hdr") | Some("pfm")
+    )
 }
@@ -5,11 +5,44 @@ use serde::{Deserialize, Serialize};
 use crate::{
     hittable::HitRecord,
     ray::Ray,
+    sampler::Sampler,
+    texture::{normal::NormalMapping, ColorInput},
     vectors::{Color, Vec3},
 };
+
+pub mod pbr;
+
 #[typetag::serde(tag = "type")]
 pub trait Material: Debug + MaterialClone + Send + Sync {
-    fn scatter(&self, r_in: Ray, rec: &HitRecord) -> Option<(Ray, Color)>;
+    fn scatter(
+        &self,
+        r_in: Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)>;
+    /// Radiance emitted by the surface at the hit point. Black unless the material is a light.
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::new_all(0.0)
+    }
+    /// Base color at the hit point, for previews and auxiliary outputs.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new_all(1.0)
+    }
+    /// Whether `emitted` can be anything but black, so objects with this material are lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+    /// For materials that scatter over a continuum of directions: the BSDF times the cosine
+    /// term for light arriving from `direction`, and the pdf with which `scatter` would have
+    /// picked that direction. `None` for specular materials, which lights cannot be sampled for.
+    fn bsdf(&self, _r_in: Ray, _rec: &HitRecord, _direction: Vec3) -> Option<(Color, f32)> {
+        None
+    }
+    /// Normal to shade with, given the surface's unit outward `normal` at the hit point.
+    /// Materials with normal or bump maps perturb it.
+    fn shading_normal(&self, _rec: &HitRecord, normal: Vec3) -> Vec3 {
+        normal
+    }
 }
 pub trait MaterialClone {
     fn clone_box(&self) -> Box<dyn Material>;
@@ -25,59 +58,217 @@ where
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Diffuse {
-    pub albedo: Color,
+    pub albedo: ColorInput,
+    #[serde(flatten)]
+    pub normals: NormalMapping,
 }
 #[typetag::serde(name = "diffuse")]
 impl Material for Diffuse {
-    fn scatter(&self, _r_in: Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+    fn scatter(
+        &self,
+        _r_in: Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
+        let (u1, u2) = sampler.get_2d();
+        let mut scatter_direction = rec.normal + Vec3::unit_vector_from(u1, u2);
         if scatter_direction.near_zero() {
             scatter_direction = rec.normal;
         }
 
         let scattered = Ray::new(rec.p, scatter_direction);
-        let attenuation = self.albedo;
-        return Some((scattered, attenuation));
+        let attenuation = self.albedo.at(rec);
+        Some((scattered, attenuation))
+    }
+    fn bsdf(&self, _r_in: Ray, rec: &HitRecord, direction: Vec3) -> Option<(Color, f32)> {
+        let cosine = rec.normal.dot(direction.normalize()).max(0.0);
+        let pdf = cosine / std::f32::consts::PI;
+        Some((self.albedo.at(rec) * Vec3::new_all(pdf), pdf))
+    }
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.at(rec)
+    }
+    fn shading_normal(&self, rec: &HitRecord, normal: Vec3) -> Vec3 {
+        self.normals.apply(rec, normal)
     }
 }
 
 impl Diffuse {
     pub fn new(albedo: Color) -> Self {
-        Self { albedo }
-    }
-    pub fn empty() -> Self {
         Self {
-            albedo: Color::new_all(0.0),
+            albedo: albedo.into(),
+            normals: NormalMapping::default(),
         }
     }
+    pub fn empty() -> Self {
+        Self::new(Color::new_all(0.0))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Metal {
-    albedo: Color,
+    pub albedo: ColorInput,
     fuzzy: f32,
+    #[serde(flatten)]
+    pub normals: NormalMapping,
 }
 impl Metal {
     pub fn new(color: Color, fuzzy: f32) -> Self {
         Self {
-            albedo: color,
+            albedo: color.into(),
             fuzzy,
+            normals: NormalMapping::default(),
         }
     }
 }
 #[typetag::serde(name = "metal")]
 impl Material for Metal {
-    fn scatter(&self, r_in: Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+    fn scatter(
+        &self,
+        r_in: Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
         let reflected = (r_in.direction).normalize().reflect(rec.normal);
+        let (u1, u2) = sampler.get_2d();
         let scattered = Ray::new(
             rec.p,
-            reflected + (Vec3::new_all(self.fuzzy) * Vec3::random_unit_vector()),
+            reflected + (Vec3::new_all(self.fuzzy) * Vec3::unit_vector_from(u1, u2)),
         );
-        let attenuation = self.albedo;
+        let attenuation = self.albedo.at(rec);
         if scattered.direction.dot(rec.normal) > 0.0 {
-            return Some((scattered, attenuation));
+            Some((scattered, attenuation))
         } else {
-            return None;
+            None
         }
     }
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.at(rec)
+    }
+    fn shading_normal(&self, rec: &HitRecord, normal: Vec3) -> Vec3 {
+        self.normals.apply(rec, normal)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dielectric {
+    /// Index of refraction
+    ir: f32,
+}
+impl Dielectric {
+    pub fn new(ir: f32) -> Self {
+        Self { ir }
+    }
+    /// Schlick's approximation for reflectance.
+    fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
+        let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
+        let r0 = r0 * r0;
+        r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+    }
+}
+#[typetag::serde(name = "dielectric")]
+impl Material for Dielectric {
+    fn scatter(
+        &self,
+        r_in: Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
+        let refraction_ratio = if rec.front_face {
+            1.0 / self.ir
+        } else {
+            self.ir
+        };
+
+        let unit_direction = r_in.direction.normalize();
+        let cos_theta = (-unit_direction).dot(rec.normal).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+        // Total internal reflection, or a Fresnel reflection picked at random.
+        let cannot_refract = refraction_ratio * sin_theta > 1.0;
+        let direction = if cannot_refract
+            || Dielectric::reflectance(cos_theta, refraction_ratio) > sampler.get_1d()
+        {
+            unit_direction.reflect(rec.normal)
+        } else {
+            unit_direction.refract(rec.normal, refraction_ratio)
+        };
+
+        Some((Ray::new(rec.p, direction), Color::new_all(1.0)))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffuseLight {
+    emit: Color,
+}
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self { emit }
+    }
+}
+#[typetag::serde(name = "diffuse_light")]
+impl Material for DiffuseLight {
+    fn scatter(
+        &self,
+        _r_in: Ray,
+        _rec: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
+        None
+    }
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        self.emit
+    }
+    fn is_emissive(&self) -> bool {
+        self.emit != Color::new_all(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sampler::IndependentSampler, vectors::Point3};
+
+    /// Sines of the angles to the normal of the rays `Dielectric::scatter` refracts for a
+    /// ray arriving at `sin_theta` from the normal, on the front or back of the surface.
+    fn refracted_sines(ir: f32, sin_theta: f32, front_face: bool) -> Vec<f32> {
+        let mut rec = HitRecord::empty();
+        rec.normal = Vec3::new(0.0, 1.0, 0.0);
+        rec.front_face = front_face;
+        let cos_theta = (1.0 - sin_theta * sin_theta).sqrt();
+        let r = Ray::new(Point3::new_all(0.0), Vec3::new(sin_theta, -cos_theta, 0.0));
+        let glass = Dielectric::new(ir);
+        let mut sampler = IndependentSampler::new(0);
+        (0..1000)
+            .filter_map(|i| {
+                sampler.start_pixel_sample(0, 0, i);
+                let (scattered, attenuation) = glass.scatter(r, &rec, &mut sampler).unwrap();
+                assert_eq!(attenuation, Color::new_all(1.0));
+                let d = scattered.direction.normalize();
+                // Reflections stay on the side of the normal, refractions cross it.
+                if d.y > 0.0 {
+                    assert!((d.x - sin_theta).abs() < 1e-5);
+                    None
+                } else {
+                    Some(d.x)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dielectric_scatter() {
+        // Entering glass bends the ray towards the normal, following Snell's law.
+        let entering = refracted_sines(1.5, 0.6, true);
+        assert!(entering.len() > 900);
+        assert!(entering.iter().all(|s| (s - 0.4).abs() < 1e-5));
+        // Leaving it bends the ray away from the normal.
+        let exiting = refracted_sines(1.5, 0.4, false);
+        assert!(exiting.len() > 900);
+        assert!(exiting.iter().all(|s| (s - 0.6).abs() < 1e-5));
+        // Past the critical angle of asin(1 / 1.5) everything is reflected.
+        assert!(refracted_sines(1.5, 0.7, false).is_empty());
+        assert!(!refracted_sines(1.5, 0.7, true).is_empty());
+    }
 }
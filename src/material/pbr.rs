@@ -0,0 +1,344 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    environment::luminance,
+    hittable::HitRecord,
+    material::Material,
+    ray::Ray,
+    sampler::Sampler,
+    texture::{
+        normal::{tangent_frame, NormalMapping},
+        ColorInput, Texture,
+    },
+    vectors::{Color, Vec3},
+};
+
+/// Metallic-roughness material with glTF's semantics and defaults: a GGX microfacet
+/// reflection over a Lambertian base for dielectrics, tinted by the base color for metals.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Pbr {
+    #[serde(default = "default_base_color")]
+    pub base_color: ColorInput,
+    /// 0 for dielectrics, 1 for metals
+    #[serde(default = "one")]
+    pub metallic: f32,
+    /// Perceptual roughness, squared to get the GGX alpha
+    #[serde(default = "one")]
+    pub roughness: f32,
+    /// Multiplies `roughness` by its green channel and `metallic` by its blue one, like
+    /// glTF's metallic-roughness texture
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metallic_roughness: Option<Box<dyn Texture>>,
+    /// Strength of the dielectric reflection, like glTF's specular factor
+    #[serde(default = "one")]
+    pub specular: f32,
+    /// Tints the dielectric reflection at normal incidence
+    #[serde(default = "default_specular_color")]
+    pub specular_color: Color,
+    /// Stretches highlights along the anisotropy direction, from 0 for none to 1
+    #[serde(default)]
+    pub anisotropy: f32,
+    /// Angle in radians of the anisotropy direction, from the u tangent towards the top of
+    /// the texture
+    #[serde(default)]
+    pub anisotropy_rotation: f32,
+    #[serde(flatten)]
+    pub normals: NormalMapping,
+}
+fn default_base_color() -> ColorInput {
+    ColorInput::Color(Color::new_all(1.0))
+}
+fn default_specular_color() -> Color {
+    Color::new_all(1.0)
+}
+fn one() -> f32 {
+    1.0
+}
+impl Pbr {
+    pub fn new(base_color: Color, metallic: f32, roughness: f32) -> Pbr {
+        Pbr {
+            base_color: base_color.into(),
+            metallic,
+            roughness,
+            metallic_roughness: None,
+            specular: 1.0,
+            specular_color: default_specular_color(),
+            anisotropy: 0.0,
+            anisotropy_rotation: 0.0,
+            normals: NormalMapping::default(),
+        }
+    }
+    /// The material's parameters at a hit point.
+    fn surface(&self, rec: &HitRecord) -> Surface {
+        let (mut metallic, mut roughness) = (self.metallic, self.roughness);
+        if let Some(texture) = &self.metallic_roughness {
+            let c = texture.value(rec.u, rec.v, rec.p);
+            roughness *= c.y;
+            metallic *= c.z;
+        }
+        // Perfectly smooth surfaces would need a delta distribution.
+        let alpha = (roughness * roughness).clamp(1e-3, 1.0);
+        let alpha_t = alpha + (1.0 - alpha) * self.anisotropy * self.anisotropy;
+
+        let (t, b) = tangent_frame(rec.normal, rec.dpdu, rec.dpdv);
+        let (sin, cos) = self.anisotropy_rotation.sin_cos();
+        let (t, b) = (
+            t * Vec3::new_all(cos) + b * Vec3::new_all(sin),
+            b * Vec3::new_all(cos) - t * Vec3::new_all(sin),
+        );
+
+        // Reflectance at normal incidence of a dielectric with an index of refraction of 1.5
+        let f0 = self.specular_color * Color::new_all(0.04);
+        Surface {
+            base: self.base_color.at(rec),
+            metallic: metallic.clamp(0.0, 1.0),
+            specular: self.specular,
+            dielectric_f0: Color::new(f0.x.min(1.0), f0.y.min(1.0), f0.z.min(1.0)),
+            alpha_x: alpha_t,
+            alpha_y: alpha,
+            frame: (t, b, rec.normal),
+        }
+    }
+}
+
+/// Parameters of a `Pbr` material at a hit point, in the frame of the shading normal with
+/// x along the anisotropy direction.
+struct Surface {
+    base: Color,
+    metallic: f32,
+    specular: f32,
+    dielectric_f0: Color,
+    alpha_x: f32,
+    alpha_y: f32,
+    frame: (Vec3, Vec3, Vec3),
+}
+impl Surface {
+    fn to_local(&self, w: Vec3) -> Vec3 {
+        let (t, b, n) = self.frame;
+        Vec3::new(w.dot(t), w.dot(b), w.dot(n))
+    }
+    fn to_world(&self, w: Vec3) -> Vec3 {
+        let (t, b, n) = self.frame;
+        t * Vec3::new_all(w.x) + b * Vec3::new_all(w.y) + n * Vec3::new_all(w.z)
+    }
+    /// Probability of sampling the specular lobe rather than the diffuse one, from an
+    /// estimate of their reflectances seen from `wo`.
+    fn specular_probability(&self, wo: Vec3) -> f32 {
+        let dielectric = schlick(self.dielectric_f0, wo.z) * Color::new_all(self.specular);
+        let specular = luminance(
+            dielectric * Color::new_all(1.0 - self.metallic)
+                + schlick(self.base, wo.z) * Color::new_all(self.metallic),
+        );
+        let diffuse =
+            (1.0 - self.metallic) * (1.0 - self.layer_reflectance(wo.z)) * luminance(self.base);
+        if specular + diffuse <= 0.0 {
+            return 1.0;
+        }
+        specular / (specular + diffuse)
+    }
+    /// Largest fraction of light the dielectric layer reflects at the angle with cosine
+    /// `cosine` to the normal.
+    fn layer_reflectance(&self, cosine: f32) -> f32 {
+        let reflectance = schlick(self.dielectric_f0, cosine).max_component() * self.specular;
+        reflectance.clamp(0.0, 1.0)
+    }
+    /// BSDF times the cosine term, and the pdf of sampling `wi`, for local directions.
+    fn eval(&self, wo: Vec3, wi: Vec3) -> (Color, f32) {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return (Color::new_all(0.0), 0.0);
+        }
+        let h = (wo + wi).normalize();
+        let cos_h = wo.dot(h).max(0.0);
+        let d = self.ggx(h);
+        let specular = d * self.smith_g2(wo, wi) / (4.0 * wo.z * wi.z);
+
+        // glTF's mix of a Fresnel-weighted dielectric layer over the diffuse base, and a
+        // metal whose reflectance at normal incidence is the base color. The base gets what
+        // the layer lets through as seen from `wo` rather than from each microfacet, as
+        // otherwise grazing angles reflect more light than they receive.
+        let dielectric = schlick(self.dielectric_f0, cos_h) * Color::new_all(self.specular);
+        let metal = schlick(self.base, cos_h);
+        let transmitted = 1.0 - self.layer_reflectance(wo.z);
+        let diffuse = self.base * Color::new_all((1.0 - self.metallic) * transmitted / PI);
+        let reflection = (dielectric * Color::new_all(1.0 - self.metallic)
+            + metal * Color::new_all(self.metallic))
+            * Color::new_all(specular);
+        let f = (diffuse + reflection) * Color::new_all(wi.z);
+
+        let p = self.specular_probability(wo);
+        let specular_pdf = self.smith_g1(wo) * d / (4.0 * wo.z);
+        let pdf = p * specular_pdf + (1.0 - p) * wi.z / PI;
+        (f, pdf)
+    }
+    /// Trowbridge-Reitz (GGX) distribution of microfacet normals.
+    fn ggx(&self, h: Vec3) -> f32 {
+        let (x, y) = (h.x / self.alpha_x, h.y / self.alpha_y);
+        let d = x * x + y * y + h.z * h.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * d * d)
+    }
+    /// Smith's auxiliary function for the GGX distribution.
+    fn lambda(&self, w: Vec3) -> f32 {
+        let (x, y) = (self.alpha_x * w.x, self.alpha_y * w.y);
+        ((1.0 + (x * x + y * y) / (w.z * w.z)).sqrt() - 1.0) / 2.0
+    }
+    /// Fraction of the microfacets visible from `w`.
+    fn smith_g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+    /// Fraction of the microfacets visible from both directions, height-correlated.
+    fn smith_g2(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+    /// Samples a microfacet normal from the distribution of normals visible from `wo`
+    /// (Heitz, "Sampling the GGX Distribution of Visible Normals").
+    fn sample_visible_normal(&self, wo: Vec3, u1: f32, u2: f32) -> Vec3 {
+        let v = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalize();
+        let length_squared = v.x * v.x + v.y * v.y;
+        let t1 = if length_squared > 0.0 {
+            Vec3::new(-v.y, v.x, 0.0) / Vec3::new_all(length_squared.sqrt())
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = v.cross(t1);
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let p3 = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        let n = t1 * Vec3::new_all(p1) + t2 * Vec3::new_all(p2) + v * Vec3::new_all(p3);
+        Vec3::new(self.alpha_x * n.x, self.alpha_y * n.y, n.z.max(0.0)).normalize()
+    }
+}
+fn schlick(f0: Color, cosine: f32) -> Color {
+    let weight = (1.0 - cosine).clamp(0.0, 1.0).powi(5);
+    f0 + (Color::new_all(1.0) - f0) * Color::new_all(weight)
+}
+
+#[typetag::serde(name = "pbr")]
+impl Material for Pbr {
+    fn scatter(
+        &self,
+        r_in: Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
+        let surface = self.surface(rec);
+        let wo = surface.to_local(-r_in.direction.normalize());
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let choice = sampler.get_1d();
+        let (u1, u2) = sampler.get_2d();
+        let wi = if choice < surface.specular_probability(wo) {
+            let h = surface.sample_visible_normal(wo, u1, u2);
+            h * Vec3::new_all(2.0 * wo.dot(h)) - wo
+        } else {
+            let d = Vec3::in_unit_disk_from(u1, u2);
+            Vec3::new(d.x, d.y, (1.0 - d.x * d.x - d.y * d.y).max(0.0).sqrt())
+        };
+        let (f, pdf) = surface.eval(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let scattered = Ray::new(rec.p, surface.to_world(wi));
+        Some((scattered, f / Color::new_all(pdf)))
+    }
+    fn bsdf(&self, r_in: Ray, rec: &HitRecord, direction: Vec3) -> Option<(Color, f32)> {
+        let surface = self.surface(rec);
+        let wo = surface.to_local(-r_in.direction.normalize());
+        let wi = surface.to_local(direction.normalize());
+        Some(surface.eval(wo, wi))
+    }
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base_color.at(rec)
+    }
+    fn shading_normal(&self, rec: &HitRecord, normal: Vec3) -> Vec3 {
+        self.normals.apply(rec, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sampler::IndependentSampler, vectors::Point3};
+
+    fn hit() -> HitRecord {
+        let mut rec = HitRecord::empty();
+        rec.p = Point3::new_all(0.0);
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, -1.0, 0.0);
+        rec
+    }
+
+    /// Average of the scatter weights, which is the albedo seen from `r_in` under uniform
+    /// white light.
+    fn albedo(material: &Pbr, r_in: Ray) -> f32 {
+        let rec = hit();
+        let mut sampler = IndependentSampler::new(1);
+        let n = 20000;
+        (0..n)
+            .map(|i| {
+                sampler.start_pixel_sample(0, 0, i);
+                material
+                    .scatter(r_in, &rec, &mut sampler)
+                    .map_or(0.0, |(_, weight)| weight.x)
+            })
+            .sum::<f32>()
+            / n as f32
+    }
+
+    #[test]
+    fn test_scatter_matches_bsdf() {
+        let rec = hit();
+        let r_in = Ray::new(Point3::new(-1.0, 0.5, 2.0), Vec3::new(1.0, -0.5, -2.0));
+        let mut material = Pbr::new(Color::new(0.8, 0.5, 0.2), 0.3, 0.4);
+        material.anisotropy = 0.6;
+        material.anisotropy_rotation = 0.5;
+        let mut sampler = IndependentSampler::new(0);
+        for i in 0..200 {
+            sampler.start_pixel_sample(0, 0, i);
+            if let Some((scattered, weight)) = material.scatter(r_in, &rec, &mut sampler) {
+                let (f, pdf) = material.bsdf(r_in, &rec, scattered.direction).unwrap();
+                let expected = f / Color::new_all(pdf);
+                assert!((weight - expected).length() < 1e-3 * expected.length());
+            }
+        }
+    }
+
+    #[test]
+    fn test_energy_conservation() {
+        let straight = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let grazing = Ray::new(Point3::new(-1.0, 0.0, 0.2), Vec3::new(1.0, 0.0, -0.2));
+        // A smooth white metal reflects everything.
+        let mirror = Pbr::new(Color::new_all(1.0), 1.0, 0.05);
+        for r in &[straight, grazing] {
+            assert!((albedo(&mirror, *r) - 1.0).abs() < 0.02);
+        }
+        // Rough surfaces lose some energy to the missing multiple scattering, but never gain.
+        for material in &[
+            Pbr::new(Color::new_all(1.0), 1.0, 0.6),
+            Pbr::new(Color::new_all(1.0), 0.0, 0.3),
+            Pbr::new(Color::new_all(1.0), 0.0, 1.0),
+        ] {
+            for r in &[straight, grazing] {
+                let a = albedo(material, *r);
+                assert!(a > 0.6 && a < 1.02, "{:?} reflects {}", material, a);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pbr_from_yaml() {
+        let material: Box<dyn Material> = serde_yaml::from_str(
+            "{type: pbr, base_color: [1, 0, 0], metallic: 0, roughness: 0.5, anisotropy: 0.3}",
+        )
+        .unwrap();
+        let yaml = serde_yaml::to_string(&material).unwrap();
+        assert!(yaml.contains("anisotropy: 0.3"), "{}", yaml);
+        assert!(serde_yaml::from_str::<Box<dyn Material>>("{type: pbr}").is_ok());
+    }
+}
@@ -0,0 +1,575 @@
+use std::{str::FromStr, sync::OnceLock};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+/// Source of the uniform random numbers in [0, 1) used to render one pixel sample.
+///
+/// Each call to `get_1d` or `get_2d` moves on to the next dimension of the sample, so a
+/// sampler can correlate the same dimension across the samples of a pixel. Samples only
+/// depend on the seed, the pixel and the sample index, which keeps renders reproducible
+/// whatever order pixels are rendered in.
+pub trait Sampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+    fn get_1d(&mut self) -> f32;
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    /// Uncorrelated pseudo-random numbers
+    Independent,
+    /// Jittered strata over the samples of a pixel in each dimension
+    Stratified,
+    /// Randomly rotated Halton sequence
+    Halton,
+    /// Owen-scrambled Sobol sequence, shuffled between pairs of dimensions
+    #[default]
+    Sobol,
+    /// Owen-scrambled Sobol sequence shared by all pixels, rotated by a blue-noise mask so the
+    /// remaining error is spread evenly over the image
+    BlueNoise,
+}
+impl FromStr for SamplerKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            "blue_noise" => Ok(SamplerKind::BlueNoise),
+            _ => Err(anyhow!(
+                "unknown sampler {}, expected independent, stratified, halton, sobol or blue_noise",
+                s
+            )),
+        }
+    }
+}
+impl SamplerKind {
+    /// Creates a sampler for pixels that get at most `samples_per_pixel` samples.
+    pub fn create(&self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed, false)),
+            SamplerKind::BlueNoise => Box::new(SobolSampler::new(seed, true)),
+        }
+    }
+}
+
+/// Finalizer of MurmurHash3, to turn structured inputs into well-mixed seeds.
+fn mix(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    h
+}
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h, &v| {
+        mix(h ^ v.wrapping_add(0x9e37_79b9))
+    })
+}
+/// Maps 32 random bits to [0, 1).
+fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+/// PCG32 random number generator (O'Neill 2014).
+#[derive(Debug, Clone)]
+pub struct Pcg32 {
+    state: u64,
+}
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+    const INCREMENT: u64 = 1_442_695_040_888_963_407;
+    pub fn new(seed: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(Pcg32::MULTIPLIER)
+            .wrapping_add(Pcg32::INCREMENT);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+    pub fn next_f32(&mut self) -> f32 {
+        to_unit(self.next_u32())
+    }
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Pcg32,
+}
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            seed,
+            rng: Pcg32::new(seed),
+        }
+    }
+}
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.rng = Pcg32::new(hash(&[self.seed, x as u64, y as u64, index as u64]));
+    }
+    fn get_1d(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.rng.next_f32(), self.rng.next_f32())
+    }
+}
+
+/// Permutes `i` in [0, `n`) pseudo-randomly (Kensler, "Correlated Multi-Jittered Sampling").
+fn permute(mut i: u32, n: u32, seed: u32) -> u32 {
+    if n <= 1 {
+        return 0;
+    }
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+/// Splits each dimension into one stratum per sample and jitters the samples within them.
+/// Two-dimensional samples use correlated multi-jittering (Kensler 2013) when the sample count
+/// is square and Latin hypercube sampling otherwise.
+pub struct StratifiedSampler {
+    seed: u64,
+    samples_per_pixel: u32,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32,
+    rng: Pcg32,
+}
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> StratifiedSampler {
+        StratifiedSampler {
+            seed,
+            samples_per_pixel: samples_per_pixel.max(1),
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+            rng: Pcg32::new(seed),
+        }
+    }
+    /// The stratum of the current sample in a random permutation for the next dimension.
+    fn stratum(&mut self) -> u32 {
+        let seed = hash(&[self.pixel_seed, self.dimension as u64]) as u32;
+        self.dimension += 1;
+        permute(
+            self.index % self.samples_per_pixel,
+            self.samples_per_pixel,
+            seed,
+        )
+    }
+}
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_seed = hash(&[self.seed, x as u64, y as u64]);
+        self.index = index;
+        self.dimension = 0;
+        self.rng = Pcg32::new(hash(&[self.pixel_seed, index as u64]));
+    }
+    fn get_1d(&mut self) -> f32 {
+        let stratum = self.stratum();
+        (stratum as f32 + self.rng.next_f32()) / self.samples_per_pixel as f32
+    }
+    fn get_2d(&mut self) -> (f32, f32) {
+        let n = self.samples_per_pixel;
+        let side = (n as f32).sqrt() as u32;
+        if side * side == n {
+            // Correlated multi-jittering: the cell within each grid cell is offset by a
+            // permutation of the other axis, so both axes also get one stratum per sample.
+            let stratum = self.stratum();
+            let (sx, sy) = (stratum % side, stratum / side);
+            let seed = hash(&[self.pixel_seed, self.dimension as u64]);
+            self.dimension += 1;
+            let jx = permute(sy, side, seed as u32);
+            let jy = permute(sx, side, (seed >> 32) as u32);
+            (
+                (sx as f32 + (jx as f32 + self.rng.next_f32()) / side as f32) / side as f32,
+                (sy as f32 + (jy as f32 + self.rng.next_f32()) / side as f32) / side as f32,
+            )
+        } else {
+            (self.get_1d(), self.get_1d())
+        }
+    }
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+fn radical_inverse(base: u32, mut i: u32) -> f32 {
+    let inverse_base = 1.0 / base as f64;
+    let mut reversed = 0u64;
+    let mut inverse_base_n = 1.0;
+    while i > 0 {
+        let next = i / base;
+        reversed = reversed * base as u64 + (i - next * base) as u64;
+        inverse_base_n *= inverse_base;
+        i = next;
+    }
+    ((reversed as f64 * inverse_base_n) as f32).min(1.0 - f32::EPSILON)
+}
+
+/// Halton sequence with a random per-pixel offset in every dimension (Cranley-Patterson
+/// rotation). Dimensions past the first 64 primes fall back to independent numbers.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: usize,
+    rng: Pcg32,
+}
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            seed,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+            rng: Pcg32::new(seed),
+        }
+    }
+}
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_seed = hash(&[self.seed, x as u64, y as u64]);
+        self.index = index;
+        self.dimension = 0;
+        self.rng = Pcg32::new(hash(&[self.pixel_seed, index as u64]));
+    }
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        if dimension >= PRIMES.len() {
+            return self.rng.next_f32();
+        }
+        let offset = to_unit(hash(&[self.pixel_seed, dimension as u64]) as u32);
+        let value = radical_inverse(PRIMES[dimension], self.index) + offset;
+        if value >= 1.0 {
+            value - 1.0
+        } else {
+            value
+        }
+    }
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Laine-Karras style hash that only lets bits affect higher bits.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+/// Owen scrambling of the bits of `x`, from the most significant down.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+/// Second dimension of the Sobol sequence; the first is `i.reverse_bits()`.
+fn sobol_dimension_1(mut i: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    let mut result = 0;
+    while i != 0 {
+        if i & 1 != 0 {
+            result ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+/// Owen-scrambled Sobol points (Burley, "Practical Hash-based Owen Scrambling"). Every pair
+/// of dimensions uses the first two Sobol dimensions with its own shuffle of the sample
+/// indices, which keeps the points well distributed in each 2D projection.
+///
+/// With `blue_noise`, all pixels share the same points and are instead offset by a blue-noise
+/// mask value (Georgiev and Fajardo, "Blue-noise Dithered Sampling").
+pub struct SobolSampler {
+    seed: u64,
+    blue_noise: bool,
+    pixel: (u32, u32),
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32,
+}
+impl SobolSampler {
+    pub fn new(seed: u64, blue_noise: bool) -> SobolSampler {
+        SobolSampler {
+            seed,
+            blue_noise,
+            pixel: (0, 0),
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+    fn next_dimension_seed(&mut self) -> u64 {
+        let seed = hash(&[self.pixel_seed, self.dimension as u64]);
+        self.dimension += 1;
+        seed
+    }
+    /// Offsets `value` by the blue-noise mask, shifted differently for each dimension.
+    fn rotate(&self, value: f32, dimension_seed: u64, axis: u64) -> f32 {
+        if !self.blue_noise {
+            return value;
+        }
+        let shift = hash(&[dimension_seed, axis]);
+        let x = (self.pixel.0 as u64 + (shift & 0xffff)) as usize % BLUE_NOISE_SIZE;
+        let y = (self.pixel.1 as u64 + (shift >> 16 & 0xffff)) as usize % BLUE_NOISE_SIZE;
+        let value = value + blue_noise_mask()[y * BLUE_NOISE_SIZE + x];
+        if value >= 1.0 {
+            value - 1.0
+        } else {
+            value
+        }
+    }
+}
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = (x, y);
+        self.pixel_seed = if self.blue_noise {
+            hash(&[self.seed])
+        } else {
+            hash(&[self.seed, x as u64, y as u64])
+        };
+        self.index = index;
+        self.dimension = 0;
+    }
+    fn get_1d(&mut self) -> f32 {
+        let seed = self.next_dimension_seed();
+        let index = nested_uniform_scramble(self.index, seed as u32);
+        let value = to_unit(nested_uniform_scramble(
+            index.reverse_bits(),
+            (seed >> 32) as u32,
+        ));
+        self.rotate(value, seed, 0)
+    }
+    fn get_2d(&mut self) -> (f32, f32) {
+        let seed = self.next_dimension_seed();
+        let index = nested_uniform_scramble(self.index, seed as u32);
+        let scramble = hash(&[seed]);
+        let x = to_unit(nested_uniform_scramble(
+            index.reverse_bits(),
+            scramble as u32,
+        ));
+        let y = to_unit(nested_uniform_scramble(
+            sobol_dimension_1(index),
+            (scramble >> 32) as u32,
+        ));
+        (self.rotate(x, seed, 0), self.rotate(y, seed, 1))
+    }
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+/// Tileable blue-noise mask with values in (0, 1), generated once on first use.
+fn blue_noise_mask() -> &'static [f32] {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5))
+}
+/// Ulichney's void-and-cluster method: ranks the pixels of a `size` x `size` torus so that
+/// every prefix of the ranking is evenly spread out.
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<f32> {
+    let n = size * size;
+    // Gaussian energy contributed by a pixel at each toroidal offset
+    let kernel = (0..n)
+        .map(|i| {
+            let wrap = |d: usize| d.min(size - d) as f32;
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect::<Vec<_>>();
+    let splat = |energy: &mut [f32], p: usize, sign: f32| {
+        let (px, py) = (p % size, p / size);
+        for (q, e) in energy.iter_mut().enumerate() {
+            let dx = (q % size + size - px) % size;
+            let dy = (q / size + size - py) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    let find = |energy: &[f32], pattern: &[bool], want: bool, largest: bool| {
+        let mut best = usize::MAX;
+        for i in (0..n).filter(|&i| pattern[i] == want) {
+            if best == usize::MAX
+                || (largest && energy[i] > energy[best])
+                || (!largest && energy[i] < energy[best])
+            {
+                best = i;
+            }
+        }
+        best
+    };
+
+    // Start from a random pattern and move points from clusters into voids until stable.
+    let mut rng = Pcg32::new(0);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let initial = n / 10;
+    let mut placed = 0;
+    while placed < initial {
+        let p = rng.next_u32() as usize % n;
+        if !pattern[p] {
+            pattern[p] = true;
+            splat(&mut energy, p, 1.0);
+            placed += 1;
+        }
+    }
+    loop {
+        let cluster = find(&energy, &pattern, true, true);
+        pattern[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = find(&energy, &pattern, false, false);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+    // Rank the initial points by removing the most clustered first.
+    let (mut ones, mut ones_energy) = (pattern.clone(), energy.clone());
+    for r in (0..initial).rev() {
+        let cluster = find(&ones_energy, &ones, true, true);
+        ones[cluster] = false;
+        splat(&mut ones_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+    // Fill the largest voids up to half of the pixels.
+    for r in initial..n / 2 {
+        let void = find(&energy, &pattern, false, false);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+    // Past half, the remaining pixels are the minority, so fill their tightest clusters.
+    let mut zeros_energy = vec![0.0; n];
+    for p in (0..n).filter(|&p| !pattern[p]) {
+        splat(&mut zeros_energy, p, 1.0);
+    }
+    for r in n / 2..n {
+        let cluster = find(&zeros_energy, &pattern, false, true);
+        pattern[cluster] = true;
+        splat(&mut zeros_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+    rank.iter().map(|&r| (r as f32 + 0.5) / n as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(kind: SamplerKind, seed: u64, n: u32) -> Vec<(f32, f32, f32)> {
+        let mut sampler = kind.create(seed, n);
+        (0..n)
+            .map(|i| {
+                sampler.start_pixel_sample(3, 5, i);
+                let (a, b) = sampler.get_2d();
+                (a, b, sampler.get_1d())
+            })
+            .collect()
+    }
+
+    const ALL: [SamplerKind; 5] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+        SamplerKind::BlueNoise,
+    ];
+
+    #[test]
+    fn test_samplers_are_deterministic_and_in_range() {
+        for kind in &ALL {
+            let a = samples(*kind, 7, 64);
+            assert_eq!(a, samples(*kind, 7, 64), "{:?}", kind);
+            assert_ne!(a, samples(*kind, 8, 64), "{:?}", kind);
+            for (x, y, z) in a {
+                for v in &[x, y, z] {
+                    assert!((0.0..1.0).contains(v), "{:?} gave {}", kind, v);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_samplers_stratify_first_dimensions() {
+        // With 16 samples, these samplers put one sample into each sixteenth of [0, 1) in
+        // the first dimension.
+        for kind in &ALL[1..4] {
+            let mut strata = [0; 16];
+            for (x, _, _) in samples(*kind, 1, 16) {
+                strata[(x * 16.0) as usize] += 1;
+            }
+            assert!(strata.iter().all(|&c| c == 1), "{:?}: {:?}", kind, strata);
+        }
+        // The blue-noise offset shifts the strata, but no gap between samples can exceed two
+        // of them.
+        let mut xs = samples(SamplerKind::BlueNoise, 1, 16)
+            .iter()
+            .map(|s| s.0)
+            .collect::<Vec<_>>();
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        xs.push(xs[0] + 1.0);
+        assert!(xs.windows(2).all(|w| w[1] - w[0] <= 2.0 / 16.0), "{:?}", xs);
+    }
+
+    #[test]
+    fn test_blue_noise_mask_is_a_permutation() {
+        let mask = void_and_cluster(16, 1.5);
+        let mut ranks = mask
+            .iter()
+            .map(|v| (v * 256.0) as usize)
+            .collect::<Vec<_>>();
+        ranks.sort_unstable();
+        assert_eq!(ranks, (0..256).collect::<Vec<_>>());
+    }
+}
@@ -1 +1,4 @@
+pub mod mesh;
+pub mod obj;
 pub mod sphere;
+pub mod triangle;
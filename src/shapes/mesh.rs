@@ -0,0 +1,346 @@
+use std::{convert::TryFrom, fs::read, path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aabb::Aabb,
+    bvh::{Bvh, SplitMethod},
+    hittable::{HitRecord, Hittable},
+    material::Material,
+    ray::Ray,
+    shapes::triangle,
+    vectors::{Point3, Vec3},
+};
+
+/// Shared vertex and index buffers of a triangle mesh. `normals` and `uvs` are either
+/// empty or indexed like `positions`. Like in glTF, v = 0 is the top row of an image
+/// texture; importers of formats with v pointing up flip it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub normals: Vec<Vec3>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uvs: Vec<(f32, f32)>,
+    pub indices: Vec<[usize; 3]>,
+}
+impl MeshData {
+    fn validate(&self) -> Result<()> {
+        if !self.normals.is_empty() && self.normals.len() != self.positions.len() {
+            return Err(anyhow!(
+                "mesh has {} positions but {} normals",
+                self.positions.len(),
+                self.normals.len()
+            ));
+        }
+        if !self.uvs.is_empty() && self.uvs.len() != self.positions.len() {
+            return Err(anyhow!(
+                "mesh has {} positions but {} uvs",
+                self.positions.len(),
+                self.uvs.len()
+            ));
+        }
+        if let Some(index) = self
+            .indices
+            .iter()
+            .flatten()
+            .find(|&&i| i >= self.positions.len())
+        {
+            return Err(anyhow!(
+                "mesh index {} is out of range for {} positions",
+                index,
+                self.positions.len()
+            ));
+        }
+        Ok(())
+    }
+    fn triangle(&self, i: usize) -> [Point3; 3] {
+        let [a, b, c] = self.indices[i];
+        [self.positions[a], self.positions[b], self.positions[c]]
+    }
+}
+
+/// An indexed triangle mesh with its own BVH over its triangles.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(try_from = "MeshDesc", into = "MeshDesc")]
+pub struct Mesh {
+    /// File the buffers were loaded from, if they were not given inline
+    file: Option<PathBuf>,
+    data: Arc<MeshData>,
+    bvh: Arc<Bvh>,
+    /// Running total of the triangles' areas, for picking them in proportion to their area
+    cumulative_areas: Arc<Vec<f32>>,
+    material: Box<dyn Material>,
+    material_id: u32,
+}
+/// A mesh as written in the scene: either inline buffers, or a `file` holding them in YAML.
+#[derive(Serialize, Deserialize)]
+struct MeshDesc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file: Option<PathBuf>,
+    #[serde(flatten)]
+    data: Option<MeshData>,
+    material: Box<dyn Material>,
+}
+impl TryFrom<MeshDesc> for Mesh {
+    type Error = anyhow::Error;
+    fn try_from(desc: MeshDesc) -> Result<Self> {
+        let data = match (&desc.file, desc.data) {
+            (Some(file), None) => serde_yaml::from_slice::<MeshData>(
+                &read(file).with_context(|| format!("failed to read mesh {:?}", file))?,
+            )?,
+            (None, Some(data)) => data,
+            (Some(_), Some(_)) => {
+                return Err(anyhow!(
+                    "a mesh needs either a file or inline buffers, not both"
+                ))
+            }
+            (None, None) => return Err(anyhow!("a mesh needs either a file or inline buffers")),
+        };
+        let mut mesh = Mesh::new(data, desc.material)?;
+        mesh.file = desc.file;
+        Ok(mesh)
+    }
+}
+impl From<Mesh> for MeshDesc {
+    fn from(mesh: Mesh) -> Self {
+        MeshDesc {
+            data: match mesh.file {
+                Some(_) => None,
+                None => Some((*mesh.data).clone()),
+            },
+            file: mesh.file,
+            material: mesh.material,
+        }
+    }
+}
+impl Mesh {
+    pub fn new(data: MeshData, material: Box<dyn Material>) -> Result<Mesh> {
+        data.validate()?;
+        let boxes = (0..data.indices.len())
+            .map(|i| triangle::bounding_box(data.triangle(i)))
+            .collect::<Vec<_>>();
+        let cumulative_areas = (0..data.indices.len())
+            .scan(0.0, |total, i| {
+                let [p0, p1, p2] = data.triangle(i);
+                *total += 0.5 * (p1 - p0).cross(p2 - p0).length();
+                Some(*total)
+            })
+            .collect();
+        Ok(Mesh {
+            file: None,
+            bvh: Arc::new(Bvh::new(&boxes, SplitMethod::Sah)),
+            cumulative_areas: Arc::new(cumulative_areas),
+            data: Arc::new(data),
+            material,
+            material_id: 0,
+        })
+    }
+    pub fn triangle_count(&self) -> usize {
+        self.data.indices.len()
+    }
+    pub fn area(&self) -> f32 {
+        self.cumulative_areas.last().copied().unwrap_or(0.0)
+    }
+    /// Index of the first triangle hit by `r`, and the distance along it.
+    fn closest_triangle(&self, r: Ray) -> Option<(usize, f32)> {
+        let mut closest = 0;
+        let rec = self.bvh.hit(r, 0.0, f32::MAX, |i, t_min, t_max| {
+            let rec = self.hit_triangle(i, r, t_min, t_max);
+            if rec.is_some() {
+                closest = i;
+            }
+            rec
+        })?;
+        Some((closest, rec.t))
+    }
+    /// Solid angle pdf of sampling the point at `offset` from the shading point on
+    /// triangle `i`, when points are picked uniformly over the whole mesh.
+    fn area_to_solid_angle(&self, i: usize, offset: Vec3) -> f32 {
+        let [p0, p1, p2] = self.data.triangle(i);
+        let normal = (p1 - p0).cross(p2 - p0).normalize();
+        let distance_squared = offset.length_squared();
+        let cosine = normal.dot(offset).abs() / distance_squared.sqrt();
+        if cosine < 1e-6 {
+            return 0.0;
+        }
+        distance_squared / (cosine * self.area())
+    }
+    fn hit_triangle(&self, i: usize, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let positions = self.data.triangle(i);
+        let hit = triangle::intersect(positions[0], positions[1], positions[2], r, t_min, t_max)?;
+        let [a, b, c] = self.data.indices[i];
+        let normals = if self.data.normals.is_empty() {
+            None
+        } else {
+            Some([
+                self.data.normals[a],
+                self.data.normals[b],
+                self.data.normals[c],
+            ])
+        };
+        let uvs = if self.data.uvs.is_empty() {
+            None
+        } else {
+            Some([self.data.uvs[a], self.data.uvs[b], self.data.uvs[c]])
+        };
+        let mut rec = triangle::hit_record(positions, normals, uvs, r, hit, &*self.material);
+        rec.material_id = self.material_id;
+        Some(rec)
+    }
+}
+#[typetag::serde(name = "mesh")]
+impl Hittable for Mesh {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max, |i, t_min, t_max| {
+            self.hit_triangle(i, r, t_min, t_max)
+        })
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounding_box()
+    }
+    fn is_light(&self) -> bool {
+        self.material.is_emissive() && self.area() > 0.0
+    }
+    /// Picks a triangle in proportion to its area and a point uniformly on it. Points
+    /// hidden behind other triangles of the mesh are rejected, so that `pdf` only needs
+    /// the first triangle along a direction.
+    fn sample(&self, origin: Point3, u1: f32, u2: f32) -> Option<(Vec3, f32)> {
+        let target = u1 * self.area();
+        let i = self
+            .cumulative_areas
+            .partition_point(|&total| total <= target)
+            .min(self.triangle_count() - 1);
+        let start = if i == 0 {
+            0.0
+        } else {
+            self.cumulative_areas[i - 1]
+        };
+        // Reuse what is left of u1 to pick the point on the triangle.
+        let width = self.cumulative_areas[i] - start;
+        let u1 = if width > 0.0 {
+            ((target - start) / width).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let direction = triangle::sample_point(self.data.triangle(i), u1, u2) - origin;
+        match self.closest_triangle(Ray::new(origin, direction)) {
+            Some((closest, t)) if closest == i || t > 1.0 - 1e-4 => {}
+            _ => return None,
+        }
+        let pdf = self.area_to_solid_angle(i, direction);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some((direction, pdf))
+    }
+    fn pdf(&self, origin: Point3, direction: Vec3) -> f32 {
+        match self.closest_triangle(Ray::new(origin, direction)) {
+            Some((i, t)) => self.area_to_solid_angle(i, direction * Vec3::new_all(t)),
+            None => 0.0,
+        }
+    }
+    fn assign_material_ids(&mut self, next_id: &mut u32) {
+        self.material_id = *next_id;
+        *next_id += 1;
+    }
+}
+impl Clone for Mesh {
+    fn clone(&self) -> Mesh {
+        Mesh {
+            file: self.file.clone(),
+            data: self.data.clone(),
+            bvh: self.bvh.clone(),
+            cumulative_areas: self.cumulative_areas.clone(),
+            material: (*self.material).clone_box(),
+            material_id: self.material_id,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittablelist::HittableList,
+        material::{Diffuse, DiffuseLight},
+        vectors::Color,
+    };
+
+    fn quad() -> Mesh {
+        quad_with(Box::new(Diffuse::empty()))
+    }
+    fn quad_with(material: Box<dyn Material>) -> Mesh {
+        Mesh::new(
+            MeshData {
+                positions: vec![
+                    Point3::new(-1.0, -1.0, 0.0),
+                    Point3::new(1.0, -1.0, 0.0),
+                    Point3::new(1.0, 1.0, 0.0),
+                    Point3::new(-1.0, 1.0, 0.0),
+                ],
+                normals: Vec::new(),
+                uvs: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+                indices: vec![[0, 1, 2], [0, 2, 3]],
+            },
+            material,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mesh_hit() {
+        let mesh = quad();
+        let r = Ray::new(Point3::new(0.5, -0.25, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = mesh
+            .hit(r, 0.001, f32::MAX)
+            .expect("ray should hit the quad");
+        assert!((rec.t - 5.0).abs() < 1e-5);
+        assert!((rec.u - 0.75).abs() < 1e-5);
+        assert!((rec.v - 0.375).abs() < 1e-5);
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        let r = Ray::new(Point3::new(1.5, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(mesh.hit(r, 0.001, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_mesh_rejects_bad_indices() {
+        let data = MeshData {
+            positions: vec![Point3::new_all(0.0); 3],
+            indices: vec![[0, 1, 3]],
+            ..MeshData::default()
+        };
+        assert!(Mesh::new(data, Box::new(Diffuse::empty())).is_err());
+    }
+
+    #[test]
+    fn test_emissive_mesh_sampling() {
+        assert!(!quad().is_light());
+        let light = quad_with(Box::new(DiffuseLight::new(Color::new_all(1.0))));
+        assert!(light.is_light());
+        let origin = Point3::new(0.2, 0.1, 2.0);
+        for i in 0..100 {
+            let (u1, u2) = (i as f32 / 100.0, (i * 37 % 100) as f32 / 100.0);
+            let (direction, pdf) = light.sample(origin, u1, u2).unwrap();
+            let rec = light
+                .hit(Ray::new(origin, direction), 0.0, f32::MAX)
+                .unwrap();
+            assert!((rec.t - 1.0).abs() < 1e-4);
+            assert!((light.pdf(origin, direction) - pdf).abs() < 1e-4 * pdf);
+        }
+        // Area 4 seen head on from distance 2: the pdf is distance² / (cos θ area) = 1.
+        let pdf = light.pdf(Point3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!((pdf - 1.0).abs() < 1e-5);
+        assert_eq!(light.pdf(origin, Vec3::new(0.0, 0.0, 1.0)), 0.0);
+
+        let mut world = HittableList::new();
+        world.add(Box::new(quad()));
+        world.add(Box::new(light));
+        assert_eq!(world.collect_lights(), 1);
+        let (object, _, pdf) = world.sample_light(origin, 0.5, 0.3, 0.6).unwrap();
+        assert_eq!(object, 1);
+        assert!(pdf > 0.0);
+    }
+}
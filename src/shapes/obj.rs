@@ -0,0 +1,428 @@
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aabb::Aabb,
+    bvh::{Bvh, SplitMethod},
+    hittable::{HitRecord, Hittable},
+    material::{Dielectric, Diffuse, DiffuseLight, Material, Metal},
+    ray::Ray,
+    shapes::mesh::{Mesh, MeshData},
+    vectors::{Color, Point3, Vec3},
+};
+
+/// A Wavefront OBJ file, loaded as one triangle mesh per group.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(try_from = "ObjDesc", into = "ObjDesc")]
+pub struct Obj {
+    desc: ObjDesc,
+    meshes: Vec<Mesh>,
+    bvh: Bvh,
+    /// Indices of the emissive meshes, sampled for direct lighting
+    lights: Vec<usize>,
+}
+#[derive(Serialize, Deserialize, Debug)]
+struct ObjDesc {
+    path: PathBuf,
+    /// Material library to use instead of the one named by `mtllib` in the OBJ file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mtl: Option<PathBuf>,
+    /// Material for groups that have none in the material library
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    material: Option<Box<dyn Material>>,
+}
+impl Clone for ObjDesc {
+    fn clone(&self) -> Self {
+        ObjDesc {
+            path: self.path.clone(),
+            mtl: self.mtl.clone(),
+            material: self.material.as_ref().map(|m| m.clone_box()),
+        }
+    }
+}
+impl TryFrom<ObjDesc> for Obj {
+    type Error = anyhow::Error;
+    fn try_from(desc: ObjDesc) -> Result<Self> {
+        let meshes = load(&desc).with_context(|| format!("failed to load {:?}", desc.path))?;
+        let boxes = meshes
+            .iter()
+            .map(|m| m.bounding_box().unwrap_or_else(Aabb::empty))
+            .collect::<Vec<_>>();
+        let lights = (0..meshes.len())
+            .filter(|&i| meshes[i].is_light())
+            .collect();
+        Ok(Obj {
+            desc,
+            bvh: Bvh::new(&boxes, SplitMethod::Sah),
+            meshes,
+            lights,
+        })
+    }
+}
+impl From<Obj> for ObjDesc {
+    fn from(obj: Obj) -> Self {
+        obj.desc
+    }
+}
+
+impl Obj {
+    /// Index of the first mesh hit by `r`.
+    fn closest_mesh(&self, r: Ray) -> Option<usize> {
+        let mut closest = 0;
+        self.bvh.hit(r, 0.0, f32::MAX, |i, t_min, t_max| {
+            let rec = self.meshes[i].hit(r, t_min, t_max);
+            if rec.is_some() {
+                closest = i;
+            }
+            rec
+        })?;
+        Some(closest)
+    }
+    fn light_area(&self) -> f32 {
+        self.lights.iter().map(|&i| self.meshes[i].area()).sum()
+    }
+}
+
+fn load(desc: &ObjDesc) -> Result<Vec<Mesh>> {
+    let options = tobj::LoadOptions {
+        triangulate: true,
+        single_index: true,
+        ..Default::default()
+    };
+    let directory = desc.path.parent().unwrap_or_else(|| Path::new("."));
+    let mut reader = BufReader::new(File::open(&desc.path)?);
+    let (models, materials) = tobj::load_obj_buf(&mut reader, &options, |mtllib| {
+        tobj::load_mtl(desc.mtl.clone().unwrap_or_else(|| directory.join(mtllib)))
+    })?;
+    let materials = match materials {
+        Ok(materials) => materials,
+        Err(e) if desc.mtl.is_some() => {
+            return Err(anyhow!("failed to load material library: {}", e));
+        }
+        Err(e) => {
+            eprintln!(
+                "Warning: {:?} has no usable material library ({}), using the fallback material",
+                desc.path, e
+            );
+            Vec::new()
+        }
+    };
+
+    let fallback = || -> Box<dyn Material> {
+        match &desc.material {
+            Some(m) => m.clone_box(),
+            None => Box::new(Diffuse::new(Color::new_all(0.8))),
+        }
+    };
+    models
+        .into_iter()
+        .filter(|model| !model.mesh.indices.is_empty())
+        .map(|model| {
+            let (name, mesh) = (model.name, model.mesh);
+            let vertex_count = mesh.positions.len() / 3;
+            let data = MeshData {
+                positions: mesh
+                    .positions
+                    .chunks_exact(3)
+                    .map(|p| Point3::new(p[0], p[1], p[2]))
+                    .collect(),
+                normals: if mesh.normals.len() == vertex_count * 3 {
+                    mesh.normals
+                        .chunks_exact(3)
+                        .map(|n| Vec3::new(n[0], n[1], n[2]))
+                        .collect()
+                } else {
+                    Vec::new()
+                },
+                // OBJ puts v = 0 at the bottom of the image, meshes at the top.
+                uvs: if mesh.texcoords.len() == vertex_count * 2 {
+                    mesh.texcoords
+                        .chunks_exact(2)
+                        .map(|t| (t[0], 1.0 - t[1]))
+                        .collect()
+                } else {
+                    Vec::new()
+                },
+                indices: mesh
+                    .indices
+                    .chunks_exact(3)
+                    .map(|i| [i[0] as usize, i[1] as usize, i[2] as usize])
+                    .collect(),
+            };
+            let material = match mesh.material_id.and_then(|id| materials.get(id)) {
+                Some(m) => convert_material(m),
+                None => fallback(),
+            };
+            Mesh::new(data, material).with_context(|| format!("in group {}", name))
+        })
+        .collect()
+}
+
+/// Picks the closest of the crate's materials for an MTL material: emissive if it has `Ke`,
+/// glass if it is transparent, metal if its specular color outweighs its diffuse color,
+/// and diffuse otherwise.
+fn convert_material(m: &tobj::Material) -> Box<dyn Material> {
+    let color = |c: [f32; 3]| Color::new(c[0], c[1], c[2]);
+    let emission = m
+        .unknown_param
+        .get("Ke")
+        .and_then(|ke| parse_color(ke))
+        .unwrap_or_else(|| Color::new_all(0.0));
+    if emission != Color::new_all(0.0) {
+        return Box::new(DiffuseLight::new(emission));
+    }
+    if m.dissolve < 1.0 {
+        // Ni defaults to 1, which would make the object invisible.
+        let ir = if m.optical_density > 1.0 {
+            m.optical_density
+        } else {
+            1.5
+        };
+        return Box::new(Dielectric::new(ir));
+    }
+    let diffuse = color(m.diffuse);
+    let specular = color(m.specular);
+    if specular.max_component() > diffuse.max_component() {
+        // Map the Phong exponent to a roughness in [0, 1].
+        let fuzzy = (2.0 / (m.shininess.max(0.0) + 2.0)).sqrt();
+        return Box::new(Metal::new(specular, fuzzy));
+    }
+    Box::new(Diffuse::new(diffuse))
+}
+fn parse_color(s: &str) -> Option<Color> {
+    let values = s
+        .split_whitespace()
+        .map(|v| v.parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    match values.as_slice() {
+        [r, g, b] => Some(Color::new(*r, *g, *b)),
+        [v] => Some(Color::new_all(*v)),
+        _ => None,
+    }
+}
+
+#[typetag::serde(name = "obj")]
+impl Hittable for Obj {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max, |i, t_min, t_max| {
+            self.meshes[i].hit(r, t_min, t_max)
+        })
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounding_box()
+    }
+    fn is_light(&self) -> bool {
+        !self.lights.is_empty()
+    }
+    /// Picks an emissive mesh in proportion to its area and samples it. Like for a single
+    /// mesh, points hidden behind other meshes of the file are rejected.
+    fn sample(&self, origin: Point3, u1: f32, u2: f32) -> Option<(Vec3, f32)> {
+        let total = self.light_area();
+        let mut target = u1 * total;
+        let mut picked = *self.lights.last()?;
+        for &i in &self.lights {
+            let area = self.meshes[i].area();
+            if target < area {
+                picked = i;
+                break;
+            }
+            target -= area;
+        }
+        let mesh = &self.meshes[picked];
+        let u1 = (target / mesh.area()).clamp(0.0, 1.0);
+        let (direction, pdf) = mesh.sample(origin, u1, u2)?;
+        if self.closest_mesh(Ray::new(origin, direction)) != Some(picked) {
+            return None;
+        }
+        Some((direction, pdf * mesh.area() / total))
+    }
+    fn pdf(&self, origin: Point3, direction: Vec3) -> f32 {
+        match self.closest_mesh(Ray::new(origin, direction)) {
+            Some(i) if self.lights.contains(&i) => {
+                self.meshes[i].pdf(origin, direction) * self.meshes[i].area() / self.light_area()
+            }
+            _ => 0.0,
+        }
+    }
+    fn assign_material_ids(&mut self, next_id: &mut u32) {
+        for mesh in &mut self.meshes {
+            mesh.assign_material_ids(next_id);
+        }
+    }
+}
+impl Clone for Obj {
+    fn clone(&self) -> Obj {
+        Obj {
+            desc: self.desc.clone(),
+            meshes: self.meshes.clone(),
+            bvh: self.bvh.clone(),
+            lights: self.lights.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor};
+
+    use super::*;
+
+    const MTL: &str = "
+newmtl matte
+Kd 0.7 0.1 0.1
+Ks 0 0 0
+
+newmtl mirror
+Kd 0.1 0.1 0.1
+Ks 0.9 0.9 0.9
+Ns 1000
+
+newmtl glass
+Kd 0 0 0
+Ni 1.33
+d 0.2
+
+newmtl lamp
+Kd 0 0 0
+Ke 4 3 2
+";
+
+    fn materials() -> Vec<tobj::Material> {
+        tobj::load_mtl_buf(&mut Cursor::new(MTL)).unwrap().0
+    }
+    fn material_type(material: &dyn Material) -> String {
+        serde_yaml::to_string(&material.clone_box())
+            .unwrap()
+            .lines()
+            .find_map(|line| line.strip_prefix("type: "))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_convert_material() {
+        let converted = materials()
+            .iter()
+            .map(|m| material_type(&*convert_material(m)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            converted,
+            ["diffuse", "metal", "dielectric", "diffuse_light"]
+        );
+        let glass = serde_yaml::to_string(&convert_material(&materials()[2])).unwrap();
+        assert!(glass.contains("1.33"), "{}", glass);
+        // A high Phong exponent makes a sharp mirror.
+        let mirror = serde_yaml::to_string(&convert_material(&materials()[1])).unwrap();
+        assert!(mirror.contains("fuzzy: 0.04"), "{}", mirror);
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("0.1 0.2 0.3"), Some(Color::new(0.1, 0.2, 0.3)));
+        assert_eq!(parse_color(" 2 "), Some(Color::new_all(2.0)));
+        assert_eq!(parse_color("1 2"), None);
+        assert_eq!(parse_color("1 x 2"), None);
+    }
+
+    #[test]
+    fn test_load_obj() {
+        let directory = std::env::temp_dir().join("raytracer_test_obj");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("scene.mtl"), MTL).unwrap();
+        fs::write(
+            directory.join("scene.obj"),
+            "mtllib scene.mtl
+v -1 0 -1
+v 1 0 -1
+v 1 0 1
+v -1 0 1
+v -1 3 -1
+v 1 3 -1
+v 1 3 1
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+g floor
+usemtl matte
+f 1/1 4/4 3/3 2/2
+g ceiling
+usemtl lamp
+f 5 6 7
+",
+        )
+        .unwrap();
+        let obj = Obj::try_from(ObjDesc {
+            path: directory.join("scene.obj"),
+            mtl: None,
+            material: None,
+        })
+        .unwrap();
+        assert_eq!(obj.meshes.len(), 2);
+        assert_eq!(obj.meshes[0].triangle_count(), 2);
+        assert!(obj.is_light());
+
+        let down = Ray::new(Point3::new(0.5, 1.0, -0.5), Vec3::new(0.0, -1.0, 0.0));
+        let rec = obj.hit(down, 0.001, f32::MAX).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-5);
+        assert_eq!(material_type(&*rec.mat), "diffuse");
+        assert!(rec.u > 0.0 && rec.u < 1.0);
+
+        // The lamp is the only light, so its samples reach it and match its pdf.
+        let origin = Point3::new(0.5, 1.0, -0.5);
+        let (direction, pdf) = obj.sample(origin, 0.3, 0.7).unwrap();
+        let rec = obj
+            .hit(Ray::new(origin, direction), 0.001, f32::MAX)
+            .unwrap();
+        assert_eq!(material_type(&*rec.mat), "diffuse_light");
+        assert!((obj.pdf(origin, direction) - pdf).abs() < 1e-4 * pdf);
+        assert_eq!(obj.pdf(origin, down.direction), 0.0);
+
+        // A material library given in the scene must load.
+        let obj = Obj::try_from(ObjDesc {
+            path: directory.join("scene.obj"),
+            mtl: Some(directory.join("missing.mtl")),
+            material: None,
+        });
+        assert!(obj.is_err());
+    }
+
+    #[test]
+    fn test_obj_texcoords_flipped() {
+        let directory = std::env::temp_dir().join("raytracer_test_obj_texcoords");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("quad.obj"),
+            "v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+f 1/1 2/2 3/3 4/4
+",
+        )
+        .unwrap();
+        let obj = Obj::try_from(ObjDesc {
+            path: directory.join("quad.obj"),
+            mtl: None,
+            material: None,
+        })
+        .unwrap();
+        // Near the top of the quad, where OBJ's v is close to 1.
+        let r = Ray::new(Point3::new(0.25, 0.9, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = obj.hit(r, 0.001, f32::MAX).unwrap();
+        assert!((rec.u - 0.25).abs() < 1e-5);
+        assert!((rec.v - 0.1).abs() < 1e-5);
+    }
+}
@@ -1,15 +1,20 @@
+use std::f32::consts::PI;
+
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    aabb::Aabb,
     hittable::{HitRecord, Hittable},
     material::Material,
-    vectors::Vec3,
+    vectors::{Point3, Vec3},
 };
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Sphere {
     center: Vec3,
     radius: f32,
     material: Box<dyn Material>,
+    #[serde(skip)]
+    material_id: u32,
 }
 impl Sphere {
     pub fn new(center: Vec3, radius: f32, material: Box<dyn Material>) -> Sphere {
@@ -17,9 +22,51 @@ impl Sphere {
             center,
             radius,
             material,
+            material_id: 0,
+        }
+    }
+    /// Axis, cosine of the half angle and solid angle of the cone the sphere subtends
+    /// from `origin`, or `None` if `origin` is inside.
+    fn cone(&self, origin: Point3) -> Option<(Vec3, f32, f32)> {
+        let to_center = self.center - origin;
+        let distance_squared = to_center.length_squared();
+        let sin2_theta_max = self.radius * self.radius / distance_squared;
+        if sin2_theta_max >= 1.0 {
+            return None;
         }
+        let cos_theta_max = (1.0 - sin2_theta_max).sqrt();
+        // 1 - cos, written to stay accurate for small, distant spheres
+        let one_minus_cos = sin2_theta_max / (1.0 + cos_theta_max);
+        Some((
+            to_center / Vec3::new_all(distance_squared.sqrt()),
+            cos_theta_max,
+            2.0 * PI * one_minus_cos,
+        ))
     }
 }
+/// Surface coordinates of the point in direction `d` from the center: `u` goes around the
+/// y axis from -x through +z, and `v` from 0 at the top (+y) to 1 at the bottom.
+fn sphere_uv(d: Vec3) -> (f32, f32) {
+    let theta = d.y.clamp(-1.0, 1.0).acos();
+    let phi = (-d.z).atan2(d.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+/// Derivatives of the point in direction `d` on a sphere of `radius` along the u and v of
+/// `sphere_uv`. At the poles, where u is undefined, any tangent frame is returned.
+fn sphere_tangents(d: Vec3, radius: f32) -> (Vec3, Vec3) {
+    let sin_theta = (d.x * d.x + d.z * d.z).sqrt();
+    if sin_theta < 1e-6 {
+        let (t, b) = d.orthonormal_basis();
+        return (
+            t * Vec3::new_all(2.0 * PI * radius),
+            b * Vec3::new_all(PI * radius),
+        );
+    }
+    let dpdu = Vec3::new(d.z, 0.0, -d.x) * Vec3::new_all(2.0 * PI * radius);
+    let dpdv = Vec3::new(d.y * d.x / sin_theta, -sin_theta, d.y * d.z / sin_theta)
+        * Vec3::new_all(PI * radius);
+    (dpdu, dpdv)
+}
 #[typetag::serde(name = "sphere")]
 impl Hittable for Sphere {
     fn hit(
@@ -47,12 +94,21 @@ impl Hittable for Sphere {
                 return None;
             }
         }
+        let p = r.at(root);
+        let d = (p - self.center).normalize();
+        let (u, v) = sphere_uv(d);
+        let (dpdu, dpdv) = sphere_tangents(d, self.radius.abs());
         let mut rec = HitRecord {
             t: root,
-            p: r.at(root),
+            p,
             normal: Vec3::new_all(0.0),
+            u,
+            v,
+            dpdu,
+            dpdv,
             front_face: false,
             mat: (*self.material).clone_box(),
+            material_id: self.material_id,
         };
         let outward_normal = (rec.p - self.center) / Vec3::new_all(self.radius);
 
@@ -60,6 +116,41 @@ impl Hittable for Sphere {
 
         Some(rec)
     }
+    fn bounding_box(&self) -> Option<Aabb> {
+        // The radius may be negative for hollow spheres.
+        let r = Vec3::new_all(self.radius.abs());
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+    /// Samples the cone of directions the sphere subtends from `origin`, which must be
+    /// outside of it.
+    fn sample(&self, origin: Point3, u1: f32, u2: f32) -> Option<(Vec3, f32)> {
+        let (axis, cos_theta_max, solid_angle) = self.cone(origin)?;
+        let cos_theta = 1.0 + u1 * (cos_theta_max - 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        let (t, b) = axis.orthonormal_basis();
+        let direction = t * Vec3::new_all(sin_theta * phi.cos())
+            + b * Vec3::new_all(sin_theta * phi.sin())
+            + axis * Vec3::new_all(cos_theta);
+        Some((direction, 1.0 / solid_angle))
+    }
+    fn pdf(&self, origin: Point3, direction: Vec3) -> f32 {
+        match self.cone(origin) {
+            Some((axis, cos_theta_max, solid_angle))
+                if direction.normalize().dot(axis) >= cos_theta_max =>
+            {
+                1.0 / solid_angle
+            }
+            _ => 0.0,
+        }
+    }
+    fn assign_material_ids(&mut self, next_id: &mut u32) {
+        self.material_id = *next_id;
+        *next_id += 1;
+    }
 }
 impl Clone for Sphere {
     fn clone(&self) -> Sphere {
@@ -67,6 +158,61 @@ impl Clone for Sphere {
             center: self.center,
             radius: self.radius,
             material: (*self.material).clone_box(),
+            material_id: self.material_id,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::DiffuseLight, ray::Ray, vectors::Color};
+
+    #[test]
+    fn test_sphere_uv() {
+        let uv = |x, y, z| sphere_uv(Vec3::new(x, y, z));
+        assert_eq!(uv(-1.0, 0.0, 0.0), (0.0, 0.5));
+        assert_eq!(uv(0.0, 0.0, 1.0), (0.25, 0.5));
+        assert_eq!(uv(1.0, 0.0, 0.0), (0.5, 0.5));
+        assert_eq!(uv(0.0, 1.0, 0.0).1, 0.0);
+        assert_eq!(uv(0.0, -1.0, 0.0).1, 1.0);
+
+        // The tangents are the derivatives of the point along u and v.
+        let d = Vec3::new(0.3, 0.5, -0.6).normalize();
+        let (dpdu, dpdv) = sphere_tangents(d, 2.0);
+        let (u, v) = sphere_uv(d);
+        let h = 1e-3;
+        let (du, dv) = (dpdu * Vec3::new_all(h / 2.0), dpdv * Vec3::new_all(h / 2.0));
+        let (u1, v1) = sphere_uv((d + du).normalize());
+        let (u2, v2) = sphere_uv((d + dv).normalize());
+        assert!((u1 - u - h).abs() < 1e-4 && (v1 - v).abs() < 1e-4);
+        assert!((u2 - u).abs() < 1e-4 && (v2 - v - h).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_sphere_sampling() {
+        let sphere = Sphere::new(
+            Point3::new(0.0, 3.0, 0.0),
+            1.0,
+            Box::new(DiffuseLight::new(Color::new_all(1.0))),
+        );
+        let origin = Point3::new(0.5, 0.0, 0.0);
+        for i in 0..100 {
+            let (u1, u2) = (i as f32 / 100.0, (i * 37 % 100) as f32 / 100.0);
+            let (direction, pdf) = sphere.sample(origin, u1, u2).unwrap();
+            assert!(sphere
+                .hit(Ray::new(origin, direction), 0.0, f32::MAX)
+                .is_some());
+            assert!((sphere.pdf(origin, direction) - pdf).abs() < 1e-4);
         }
+        // The pdf is one over the solid angle, 2π(1 - cos θ) for the subtended cone.
+        let distance_squared = 0.5f32 * 0.5 + 3.0 * 3.0;
+        let cos_theta_max = (1.0 - 1.0 / distance_squared).sqrt();
+        let expected = 1.0 / (2.0 * PI * (1.0 - cos_theta_max));
+        let towards_center = Vec3::new(-0.5, 3.0, 0.0);
+        assert!((sphere.pdf(origin, towards_center) - expected).abs() < 1e-3);
+        assert_eq!(sphere.pdf(origin, Vec3::new(0.0, -1.0, 0.0)), 0.0);
+        assert!(sphere
+            .sample(Point3::new(0.0, 3.5, 0.0), 0.5, 0.5)
+            .is_none());
     }
 }
@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    material::Material,
+    ray::Ray,
+    vectors::{Point3, Vec3},
+};
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Triangle {
+    vertices: [Point3; 3],
+    /// Per-vertex shading normals. The geometric normal is used when absent.
+    #[serde(default)]
+    normals: Option<[Vec3; 3]>,
+    /// Per-vertex texture coordinates. Barycentric coordinates are used when absent.
+    #[serde(default)]
+    uvs: Option<[(f32, f32); 3]>,
+    material: Box<dyn Material>,
+    #[serde(skip)]
+    material_id: u32,
+}
+impl Triangle {
+    pub fn new(vertices: [Point3; 3], material: Box<dyn Material>) -> Triangle {
+        Triangle {
+            vertices,
+            normals: None,
+            uvs: None,
+            material,
+            material_id: 0,
+        }
+    }
+    /// Converts the uniform area pdf to a solid angle pdf for the point at `offset` from
+    /// the shading point.
+    fn area_to_solid_angle(&self, offset: Vec3) -> f32 {
+        let [p0, p1, p2] = self.vertices;
+        let cross = (p1 - p0).cross(p2 - p0);
+        let area = 0.5 * cross.length();
+        let distance_squared = offset.length_squared();
+        let cosine = cross.normalize().dot(offset).abs() / distance_squared.sqrt();
+        if area == 0.0 || cosine < 1e-6 {
+            return 0.0;
+        }
+        distance_squared / (cosine * area)
+    }
+}
+
+/// Möller–Trumbore ray-triangle intersection. Returns `t` and the barycentric coordinates
+/// of the hit point with respect to `p1` and `p2`.
+#[inline(always)]
+pub fn intersect(
+    p0: Point3,
+    p1: Point3,
+    p2: Point3,
+    r: Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, f32, f32)> {
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let h = r.direction.cross(edge2);
+    let a = edge1.dot(h);
+    if a.abs() < 1e-12 {
+        // The ray is parallel to the triangle.
+        return None;
+    }
+    let f = 1.0 / a;
+    let s = r.origin - p0;
+    let b1 = f * s.dot(h);
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let b2 = f * r.direction.dot(q);
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    let t = f * edge2.dot(q);
+    if t < t_min || t_max < t {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+/// Fills in a hit record from the barycentric coordinates returned by `intersect`,
+/// interpolating the optional per-vertex normals and uvs.
+pub fn hit_record(
+    positions: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f32, f32); 3]>,
+    r: Ray,
+    (t, b1, b2): (f32, f32, f32),
+    material: &dyn Material,
+) -> HitRecord {
+    let b0 = 1.0 - b1 - b2;
+    let (u, v) = match uvs {
+        Some(uv) => (
+            b0 * uv[0].0 + b1 * uv[1].0 + b2 * uv[2].0,
+            b0 * uv[0].1 + b1 * uv[1].1 + b2 * uv[2].1,
+        ),
+        None => (b1, b2),
+    };
+    let geometric_normal = (positions[1] - positions[0])
+        .cross(positions[2] - positions[0])
+        .normalize();
+    let (dpdu, dpdv) = tangents(
+        positions,
+        uvs.unwrap_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]),
+        geometric_normal,
+    );
+    let mut rec = HitRecord {
+        t,
+        p: r.at(t),
+        normal: Vec3::new_all(0.0),
+        u,
+        v,
+        dpdu,
+        dpdv,
+        front_face: false,
+        mat: material.clone_box(),
+        material_id: 0,
+    };
+    let shading_normal = match normals {
+        Some(n) => (n[0] * Vec3::new_all(b0) + n[1] * Vec3::new_all(b1) + n[2] * Vec3::new_all(b2))
+            .normalize(),
+        None => geometric_normal,
+    };
+    rec.set_shading_normal(r, geometric_normal, shading_normal);
+    rec
+}
+
+/// Derivatives of the points of the triangle along u and v, from the differences of its
+/// vertices' positions and uvs. Degenerate uvs give an arbitrary frame around `normal`.
+fn tangents(positions: [Point3; 3], uvs: [(f32, f32); 3], normal: Vec3) -> (Vec3, Vec3) {
+    let (du02, dv02) = (uvs[0].0 - uvs[2].0, uvs[0].1 - uvs[2].1);
+    let (du12, dv12) = (uvs[1].0 - uvs[2].0, uvs[1].1 - uvs[2].1);
+    let (dp02, dp12) = (positions[0] - positions[2], positions[1] - positions[2]);
+    let determinant = du02 * dv12 - dv02 * du12;
+    if determinant.abs() < 1e-9 {
+        return normal.orthonormal_basis();
+    }
+    let inverse = 1.0 / determinant;
+    let dpdu = (dp02 * Vec3::new_all(dv12) - dp12 * Vec3::new_all(dv02)) * Vec3::new_all(inverse);
+    let dpdv = (dp12 * Vec3::new_all(du02) - dp02 * Vec3::new_all(du12)) * Vec3::new_all(inverse);
+    (dpdu, dpdv)
+}
+
+/// Point distributed uniformly over the triangle's area for uniform `u1` and `u2`.
+pub fn sample_point([p0, p1, p2]: [Point3; 3], u1: f32, u2: f32) -> Point3 {
+    let su = u1.sqrt();
+    let (b1, b2) = (1.0 - su, u2 * su);
+    p0 + (p1 - p0) * Vec3::new_all(b1) + (p2 - p0) * Vec3::new_all(b2)
+}
+
+pub fn bounding_box(positions: [Point3; 3]) -> Aabb {
+    let bbox = Aabb::new(positions[0], positions[0])
+        .including(positions[1])
+        .including(positions[2]);
+    // Pad the box so axis-aligned triangles still have some volume.
+    let padding = Vec3::new_all(1e-4);
+    Aabb::new(bbox.min - padding, bbox.max + padding)
+}
+
+#[typetag::serde(name = "triangle")]
+impl Hittable for Triangle {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let [p0, p1, p2] = self.vertices;
+        let hit = intersect(p0, p1, p2, r, t_min, t_max)?;
+        let mut rec = hit_record(
+            self.vertices,
+            self.normals,
+            self.uvs,
+            r,
+            hit,
+            &*self.material,
+        );
+        rec.material_id = self.material_id;
+        Some(rec)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(bounding_box(self.vertices))
+    }
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+    /// Samples a point uniformly over the triangle's area.
+    fn sample(&self, origin: Point3, u1: f32, u2: f32) -> Option<(Vec3, f32)> {
+        let direction = sample_point(self.vertices, u1, u2) - origin;
+        let pdf = self.area_to_solid_angle(direction);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some((direction, pdf))
+    }
+    fn pdf(&self, origin: Point3, direction: Vec3) -> f32 {
+        let [p0, p1, p2] = self.vertices;
+        match intersect(p0, p1, p2, Ray::new(origin, direction), 0.0, f32::MAX) {
+            Some((t, _, _)) => self.area_to_solid_angle(direction * Vec3::new_all(t)),
+            None => 0.0,
+        }
+    }
+    fn assign_material_ids(&mut self, next_id: &mut u32) {
+        self.material_id = *next_id;
+        *next_id += 1;
+    }
+}
+impl Clone for Triangle {
+    fn clone(&self) -> Triangle {
+        Triangle {
+            vertices: self.vertices,
+            normals: self.normals,
+            uvs: self.uvs,
+            material: (*self.material).clone_box(),
+            material_id: self.material_id,
+        }
+    }
+}
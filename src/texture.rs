@@ -0,0 +1,445 @@
+use std::{convert::TryFrom, fmt::Debug, path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{
+    de::{
+        value::{MapAccessDeserializer, SeqAccessDeserializer},
+        MapAccess, SeqAccess, Visitor,
+    },
+    Deserialize, Deserializer, Serialize,
+};
+
+use crate::{
+    hittable::HitRecord,
+    vectors::{Color, Point3},
+};
+
+pub mod noise;
+pub mod normal;
+
+/// A color that varies over surfaces, evaluated at surface coordinates (`u`, `v`) and at the
+/// world-space point `p`.
+#[typetag::serde(tag = "type")]
+pub trait Texture: Debug + TextureClone + Send + Sync {
+    fn value(&self, u: f32, v: f32, p: Point3) -> Color;
+}
+pub trait TextureClone {
+    fn clone_box(&self) -> Box<dyn Texture>;
+}
+impl<T> TextureClone for T
+where
+    T: 'static + Texture + Clone,
+{
+    fn clone_box(&self) -> Box<dyn Texture> {
+        Box::new(self.clone())
+    }
+}
+impl Clone for Box<dyn Texture> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// A material parameter given either as a constant color, such as `[0.5, 0.5, 0.5]`, or as
+/// a texture, such as `{type: checker, scale: 0.5}`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ColorInput {
+    Color(Color),
+    Texture(Box<dyn Texture>),
+}
+/// Picks the variant from the shape of the input, so that errors from inside a texture, such
+/// as a missing image file, are reported instead of a generic mismatch.
+impl<'de> Deserialize<'de> for ColorInput {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ColorInputVisitor;
+        impl<'de> Visitor<'de> for ColorInputVisitor {
+            type Value = ColorInput;
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a color such as [0.5, 0.5, 0.5] or a texture such as {type: checker}")
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<ColorInput, A::Error> {
+                Color::deserialize(SeqAccessDeserializer::new(seq)).map(ColorInput::Color)
+            }
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<ColorInput, A::Error> {
+                Box::<dyn Texture>::deserialize(MapAccessDeserializer::new(map))
+                    .map(ColorInput::Texture)
+            }
+        }
+        deserializer.deserialize_any(ColorInputVisitor)
+    }
+}
+impl ColorInput {
+    pub fn value(&self, u: f32, v: f32, p: Point3) -> Color {
+        match self {
+            ColorInput::Color(color) => *color,
+            ColorInput::Texture(texture) => texture.value(u, v, p),
+        }
+    }
+    /// Value at a hit point.
+    pub fn at(&self, rec: &HitRecord) -> Color {
+        self.value(rec.u, rec.v, rec.p)
+    }
+}
+impl From<Color> for ColorInput {
+    fn from(color: Color) -> Self {
+        ColorInput::Color(color)
+    }
+}
+
+/// The same color everywhere.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SolidColor {
+    pub color: Color,
+}
+#[typetag::serde(name = "solid")]
+impl Texture for SolidColor {
+    fn value(&self, _u: f32, _v: f32, _p: Point3) -> Color {
+        self.color
+    }
+}
+
+/// Alternating cubes of `scale` world units, so it needs no surface coordinates.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checker {
+    #[serde(default = "default_even")]
+    pub even: ColorInput,
+    #[serde(default = "default_odd")]
+    pub odd: ColorInput,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+fn default_even() -> ColorInput {
+    ColorInput::Color(Color::new_all(0.8))
+}
+fn default_odd() -> ColorInput {
+    ColorInput::Color(Color::new_all(0.1))
+}
+fn default_scale() -> f32 {
+    1.0
+}
+#[typetag::serde(name = "checker")]
+impl Texture for Checker {
+    fn value(&self, u: f32, v: f32, p: Point3) -> Color {
+        let cell = |x: f32| (x / self.scale).floor() as i64;
+        if (cell(p.x) + cell(p.y) + cell(p.z)).rem_euclid(2) == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// Colors surface coordinates, red for `u` and green for `v`, with dark lines every
+/// `1 / lines`, to check how a texture will be laid out.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UvGrid {
+    #[serde(default = "default_lines")]
+    pub lines: u32,
+    /// Width of the lines relative to the cells between them
+    #[serde(default = "default_line_width")]
+    pub line_width: f32,
+}
+fn default_lines() -> u32 {
+    10
+}
+fn default_line_width() -> f32 {
+    0.05
+}
+#[typetag::serde(name = "uv_grid")]
+impl Texture for UvGrid {
+    fn value(&self, u: f32, v: f32, _p: Point3) -> Color {
+        let on_line = |x: f32| {
+            let f = (x * self.lines as f32).rem_euclid(1.0);
+            f < self.line_width / 2.0 || f > 1.0 - self.line_width / 2.0
+        };
+        if on_line(u) || on_line(v) {
+            return Color::new_all(0.05);
+        }
+        Color::new(u.rem_euclid(1.0), v.rem_euclid(1.0), 0.25)
+    }
+}
+
+/// What image textures return outside [0, 1].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    /// Repeats the image mirrored every other time
+    Mirror,
+    /// Extends the edge texels
+    Clamp,
+}
+impl WrapMode {
+    fn apply(&self, i: i64, n: i64) -> usize {
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m >= n {
+                    2 * n - 1 - m
+                } else {
+                    m
+                }
+            }
+            WrapMode::Clamp => i.clamp(0, n - 1),
+        };
+        i as usize
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    Nearest,
+    /// Blends the four texels around the lookup
+    #[default]
+    Bilinear,
+}
+
+/// Decoded pixels of an image, as linear colors stored top row first.
+pub struct Texels {
+    width: usize,
+    height: usize,
+    /// Whether the image was decoded from sRGB, rather than holding data like normals
+    srgb: bool,
+    pixels: Vec<Color>,
+}
+impl Debug for Texels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Texels({}x{})", self.width, self.height)
+    }
+}
+impl Texels {
+    /// Builds texels from 8-bit RGB(A) rows, top row first, either sRGB-encoded colors or
+    /// linear data such as normals and heights.
+    pub fn from_8bit(
+        width: usize,
+        height: usize,
+        channels: usize,
+        data: &[u8],
+        srgb: bool,
+    ) -> Texels {
+        let decode = |c: u8| {
+            if srgb {
+                srgb_to_linear(c)
+            } else {
+                c as f32 / 255.0
+            }
+        };
+        let pixels = data
+            .chunks_exact(channels)
+            .map(|p| {
+                let (r, g, b) = match channels {
+                    1 | 2 => (p[0], p[0], p[0]),
+                    _ => (p[0], p[1], p[2]),
+                };
+                Color::new(decode(r), decode(g), decode(b))
+            })
+            .collect();
+        Texels {
+            width,
+            height,
+            srgb,
+            pixels,
+        }
+    }
+    /// Loads a PNG, JPEG or other 8-bit image supported by the `image` crate.
+    pub fn load(path: &PathBuf, srgb: bool) -> Result<Texels> {
+        let image = ::image::open(path)
+            .with_context(|| format!("failed to load texture {:?}", path))?
+            .to_rgb8();
+        let (width, height) = image.dimensions();
+        Ok(Texels::from_8bit(
+            width as usize,
+            height as usize,
+            3,
+            image.as_raw(),
+            srgb,
+        ))
+    }
+    fn texel(&self, x: i64, y: i64, wrap: WrapMode) -> Color {
+        let x = wrap.apply(x, self.width as i64);
+        let y = wrap.apply(y, self.height as i64);
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// An image mapped onto surface coordinates, with v = 0 at the top of the image.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "ImageTextureDesc", into = "ImageTextureDesc")]
+pub struct ImageTexture {
+    /// `None` for images embedded in imported files
+    path: Option<PathBuf>,
+    wrap: WrapMode,
+    filter: Filter,
+    /// Multiplies the texels, like glTF's base color factor
+    factor: Color,
+    texels: Arc<Texels>,
+}
+#[derive(Serialize, Deserialize)]
+struct ImageTextureDesc {
+    #[serde(default)]
+    path: Option<PathBuf>,
+    #[serde(default)]
+    wrap: WrapMode,
+    #[serde(default)]
+    filter: Filter,
+    #[serde(default = "default_factor")]
+    factor: Color,
+    /// False for images holding data rather than colors, such as normal and bump maps
+    #[serde(default = "default_srgb")]
+    srgb: bool,
+}
+fn default_factor() -> Color {
+    Color::new_all(1.0)
+}
+fn default_srgb() -> bool {
+    true
+}
+impl TryFrom<ImageTextureDesc> for ImageTexture {
+    type Error = anyhow::Error;
+    fn try_from(desc: ImageTextureDesc) -> Result<Self> {
+        let path = desc
+            .path
+            .ok_or_else(|| anyhow!("image texture needs a path"))?;
+        Ok(ImageTexture {
+            texels: Arc::new(Texels::load(&path, desc.srgb)?),
+            path: Some(path),
+            wrap: desc.wrap,
+            filter: desc.filter,
+            factor: desc.factor,
+        })
+    }
+}
+impl From<ImageTexture> for ImageTextureDesc {
+    fn from(texture: ImageTexture) -> Self {
+        ImageTextureDesc {
+            path: texture.path,
+            wrap: texture.wrap,
+            filter: texture.filter,
+            factor: texture.factor,
+            srgb: texture.texels.srgb,
+        }
+    }
+}
+impl ImageTexture {
+    /// Texture over already decoded texels, for importers.
+    pub fn new(texels: Arc<Texels>, wrap: WrapMode, filter: Filter, factor: Color) -> Self {
+        ImageTexture {
+            path: None,
+            wrap,
+            filter,
+            factor,
+            texels,
+        }
+    }
+}
+#[typetag::serde(name = "image")]
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _p: Point3) -> Color {
+        let t = &self.texels;
+        let x = u * t.width as f32;
+        let y = v * t.height as f32;
+        let color = match self.filter {
+            Filter::Nearest => t.texel(x.floor() as i64, y.floor() as i64, self.wrap),
+            Filter::Bilinear => {
+                // Texel centers are at half-integer coordinates.
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let lerp = |a: Color, b: Color, t: f32| {
+                    a * Color::new_all(1.0 - t) + b * Color::new_all(t)
+                };
+                lerp(
+                    lerp(
+                        t.texel(x0, y0, self.wrap),
+                        t.texel(x0 + 1, y0, self.wrap),
+                        fx,
+                    ),
+                    lerp(
+                        t.texel(x0, y0 + 1, self.wrap),
+                        t.texel(x0 + 1, y0 + 1, self.wrap),
+                        fx,
+                    ),
+                    fy,
+                )
+            }
+        };
+        color * self.factor
+    }
+}
+
+pub fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_by_one(wrap: WrapMode, filter: Filter) -> ImageTexture {
+        let texels = Texels::from_8bit(2, 1, 1, &[0, 255], true);
+        ImageTexture::new(Arc::new(texels), wrap, filter, Color::new_all(1.0))
+    }
+
+    #[test]
+    fn test_image_texture_filtering_and_wrapping() {
+        let p = Point3::new_all(0.0);
+        let nearest = two_by_one(WrapMode::Repeat, Filter::Nearest);
+        assert_eq!(nearest.value(0.25, 0.5, p).x, 0.0);
+        assert_eq!(nearest.value(0.75, 0.5, p).x, 1.0);
+        assert_eq!(nearest.value(1.25, 0.5, p).x, 0.0);
+
+        // Halfway between the texel centers
+        let bilinear = two_by_one(WrapMode::Clamp, Filter::Bilinear);
+        assert!((bilinear.value(0.5, 0.5, p).x - 0.5).abs() < 1e-6);
+        assert_eq!(bilinear.value(0.0, 0.5, p).x, 0.0);
+        // Repeating blends the right edge with the left texel.
+        let repeat = two_by_one(WrapMode::Repeat, Filter::Bilinear);
+        assert!((repeat.value(1.0, 0.5, p).x - 0.5).abs() < 1e-6);
+
+        let mirror = two_by_one(WrapMode::Mirror, Filter::Nearest);
+        assert_eq!(mirror.value(1.25, 0.5, p).x, 1.0);
+        assert_eq!(mirror.value(1.75, 0.5, p).x, 0.0);
+    }
+
+    #[test]
+    fn test_color_input_from_yaml() {
+        let color: ColorInput = serde_yaml::from_str("[0.1, 0.2, 0.3]").unwrap();
+        assert_eq!(
+            color.value(0.0, 0.0, Point3::new_all(0.0)),
+            Color::new(0.1, 0.2, 0.3)
+        );
+        let checker: ColorInput =
+            serde_yaml::from_str("{type: checker, even: [1, 1, 1], odd: [0, 0, 0], scale: 2}")
+                .unwrap();
+        assert_eq!(
+            checker.value(0.0, 0.0, Point3::new(1.0, 1.0, 1.0)),
+            Color::new_all(1.0)
+        );
+        assert_eq!(
+            checker.value(0.0, 0.0, Point3::new(3.0, 1.0, 1.0)),
+            Color::new_all(0.0)
+        );
+        // Errors from inside the texture come through.
+        let error = |yaml| {
+            serde_yaml::from_str::<ColorInput>(yaml)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("{type: bogus}").contains("bogus"));
+        assert!(error("{type: image, path: missing.png}").contains("missing.png"));
+        assert!(error("[0.1, 0.2]").contains("length 2"));
+        assert!(error("0.5").contains("a color such as"));
+    }
+}
@@ -0,0 +1,300 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    sampler::Pcg32,
+    texture::Texture,
+    vectors::{Color, Point3},
+};
+
+/// Perlin's improved gradient noise, roughly in [-1, 1] and 0 at integer coordinates.
+pub fn perlin(p: Point3) -> f32 {
+    let permutation = permutation();
+    let hash = |x: i64, y: i64, z: i64| {
+        let h = permutation[(x & 255) as usize] as usize;
+        let h = permutation[(h + (y & 255) as usize) & 255] as usize;
+        permutation[(h + (z & 255) as usize) & 255]
+    };
+    let (xi, yi, zi) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (x, y, z) = (p.x - xi, p.y - yi, p.z - zi);
+    let (xi, yi, zi) = (xi as i64, yi as i64, zi as i64);
+    let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+    let lerp = |t: f32, a: f32, b: f32| a + t * (b - a);
+
+    let corner = |dx: i64, dy: i64, dz: i64| {
+        gradient(
+            hash(xi + dx, yi + dy, zi + dz),
+            x - dx as f32,
+            y - dy as f32,
+            z - dz as f32,
+        )
+    };
+    lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+            lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+        ),
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+            lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+        ),
+    )
+}
+/// Dot product of (`x`, `y`, `z`) with one of the 12 cube edge directions picked by `hash`.
+fn gradient(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = match h {
+        0..=3 => y,
+        12 | 14 => x,
+        _ => z,
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+/// Fixed random permutation of 0..256, so noise looks the same in every render.
+fn permutation() -> &'static [u8; 256] {
+    static PERMUTATION: OnceLock<[u8; 256]> = OnceLock::new();
+    PERMUTATION.get_or_init(|| {
+        let mut permutation = [0; 256];
+        for (i, p) in permutation.iter_mut().enumerate() {
+            *p = i as u8;
+        }
+        let mut rng = Pcg32::new(0);
+        for i in (1..256).rev() {
+            permutation.swap(i, rng.next_u32() as usize % (i + 1));
+        }
+        permutation
+    })
+}
+
+/// Octaves of noise at increasing frequencies and decreasing amplitudes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Fractal {
+    /// Frequency of the first octave, in cycles per world unit
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    #[serde(default = "default_octaves")]
+    pub octaves: u32,
+    /// Frequency ratio between successive octaves
+    #[serde(default = "default_lacunarity")]
+    pub lacunarity: f32,
+    /// Amplitude ratio between successive octaves
+    #[serde(default = "default_gain")]
+    pub gain: f32,
+}
+fn default_scale() -> f32 {
+    1.0
+}
+fn default_octaves() -> u32 {
+    5
+}
+fn default_lacunarity() -> f32 {
+    2.0
+}
+fn default_gain() -> f32 {
+    0.5
+}
+impl Fractal {
+    /// Sum of the octaves of `f` at `p`, divided by the sum of their amplitudes so it stays
+    /// in the range of `f`.
+    fn sum(&self, p: Point3, f: impl Fn(f32) -> f32) -> f32 {
+        let (mut total, mut norm) = (0.0, 0.0);
+        let (mut frequency, mut amplitude) = (self.scale, 1.0);
+        for _ in 0..self.octaves.max(1) {
+            total += amplitude * f(perlin(p * Point3::new_all(frequency)));
+            norm += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        total / norm
+    }
+    /// Fractional Brownian motion, in [-1, 1].
+    pub fn fbm(&self, p: Point3) -> f32 {
+        self.sum(p, |n| n)
+    }
+    /// Sum of absolute octaves, in [0, 1], with sharp creases where the noise crosses 0.
+    pub fn turbulence(&self, p: Point3) -> f32 {
+        self.sum(p, f32::abs)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ColorStop {
+    pub position: f32,
+    pub color: Color,
+}
+/// Maps [0, 1] to colors by blending between stops, given in increasing order.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ColorRamp(pub Vec<ColorStop>);
+impl ColorRamp {
+    pub fn new(stops: &[(f32, Color)]) -> ColorRamp {
+        ColorRamp(
+            stops
+                .iter()
+                .map(|&(position, color)| ColorStop { position, color })
+                .collect(),
+        )
+    }
+    pub fn at(&self, t: f32) -> Color {
+        let stops = &self.0;
+        match stops.iter().position(|s| s.position > t) {
+            None => stops.last().map_or(Color::new_all(0.0), |s| s.color),
+            Some(0) => stops[0].color,
+            Some(i) => {
+                let (a, b) = (stops[i - 1], stops[i]);
+                let f = (t - a.position) / (b.position - a.position);
+                a.color * Color::new_all(1.0 - f) + b.color * Color::new_all(f)
+            }
+        }
+    }
+}
+fn grayscale_ramp() -> ColorRamp {
+    ColorRamp::new(&[(0.0, Color::new_all(0.0)), (1.0, Color::new_all(1.0))])
+}
+
+/// Fractional Brownian motion noise, mapped from [-1, 1] through `ramp`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NoiseTexture {
+    #[serde(flatten)]
+    pub fractal: Fractal,
+    #[serde(default = "grayscale_ramp")]
+    pub ramp: ColorRamp,
+}
+#[typetag::serde(name = "noise")]
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, p: Point3) -> Color {
+        self.ramp.at(0.5 + 0.5 * self.fractal.fbm(p))
+    }
+}
+
+/// Turbulence noise mapped through `ramp`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Turbulence {
+    #[serde(flatten)]
+    pub fractal: Fractal,
+    #[serde(default = "grayscale_ramp")]
+    pub ramp: ColorRamp,
+}
+#[typetag::serde(name = "turbulence")]
+impl Texture for Turbulence {
+    fn value(&self, _u: f32, _v: f32, p: Point3) -> Color {
+        self.ramp.at(self.fractal.turbulence(p))
+    }
+}
+
+/// Veins along x, made by a sine wave whose phase is shifted by turbulence.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Marble {
+    #[serde(flatten)]
+    pub fractal: Fractal,
+    /// How much the turbulence bends the veins
+    #[serde(default = "default_marble_distortion")]
+    pub distortion: f32,
+    #[serde(default = "marble_ramp")]
+    pub ramp: ColorRamp,
+}
+fn default_marble_distortion() -> f32 {
+    4.0
+}
+fn marble_ramp() -> ColorRamp {
+    ColorRamp::new(&[
+        (0.0, Color::new(0.25, 0.25, 0.28)),
+        (0.3, Color::new(0.7, 0.7, 0.72)),
+        (1.0, Color::new(0.92, 0.91, 0.88)),
+    ])
+}
+#[typetag::serde(name = "marble")]
+impl Texture for Marble {
+    fn value(&self, _u: f32, _v: f32, p: Point3) -> Color {
+        let phase = p.x * self.fractal.scale + self.distortion * self.fractal.turbulence(p);
+        self.ramp.at(0.5 + 0.5 * phase.sin())
+    }
+}
+
+/// Growth rings around the y axis, one per `1 / scale` units, wobbled by noise.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Wood {
+    #[serde(flatten)]
+    pub fractal: Fractal,
+    /// How far, in rings, the noise moves the rings
+    #[serde(default = "default_wood_distortion")]
+    pub distortion: f32,
+    #[serde(default = "wood_ramp")]
+    pub ramp: ColorRamp,
+}
+fn default_wood_distortion() -> f32 {
+    0.5
+}
+fn wood_ramp() -> ColorRamp {
+    ColorRamp::new(&[
+        (0.0, Color::new(0.45, 0.26, 0.12)),
+        (0.7, Color::new(0.6, 0.38, 0.18)),
+        (1.0, Color::new(0.3, 0.16, 0.07)),
+    ])
+}
+#[typetag::serde(name = "wood")]
+impl Texture for Wood {
+    fn value(&self, _u: f32, _v: f32, p: Point3) -> Color {
+        let radius = (p.x * p.x + p.z * p.z).sqrt() * self.fractal.scale;
+        let rings = radius + self.distortion * self.fractal.fbm(p);
+        self.ramp.at(rings.rem_euclid(1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::ColorInput;
+
+    #[test]
+    fn test_perlin() {
+        // Zero at lattice points, smooth and bounded in between.
+        assert_eq!(perlin(Point3::new(3.0, -2.0, 7.0)), 0.0);
+        let mut previous = perlin(Point3::new(0.3, 0.4, 0.5));
+        let mut nonzero = false;
+        for i in 1..1000 {
+            let n = perlin(Point3::new(0.3 + i as f32 * 0.001, 0.4, 0.5));
+            assert!((n - previous).abs() < 0.01);
+            assert!((-1.0..=1.0).contains(&n));
+            nonzero |= n.abs() > 0.05;
+            previous = n;
+        }
+        assert!(nonzero);
+    }
+
+    #[test]
+    fn test_color_ramp() {
+        let ramp = ColorRamp::new(&[
+            (0.2, Color::new_all(0.0)),
+            (0.6, Color::new_all(1.0)),
+            (1.0, Color::new(1.0, 0.0, 0.0)),
+        ]);
+        assert_eq!(ramp.at(0.0), Color::new_all(0.0));
+        assert!((ramp.at(0.4).x - 0.5).abs() < 1e-6);
+        assert!((ramp.at(0.8).y - 0.5).abs() < 1e-6);
+        assert_eq!(ramp.at(2.0), Color::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_noise_textures_from_yaml() {
+        let marble: ColorInput = serde_yaml::from_str(
+            "{type: marble, scale: 2, octaves: 3, lacunarity: 2.5, \
+             ramp: [{position: 0, color: [0, 0, 0]}, {position: 1, color: [1, 1, 1]}]}",
+        )
+        .unwrap();
+        let yaml = serde_yaml::to_string(&marble).unwrap();
+        assert!(yaml.contains("lacunarity: 2.5"), "{}", yaml);
+        for source in &["{type: noise}", "{type: turbulence}", "{type: wood}"] {
+            let texture: ColorInput = serde_yaml::from_str(source).unwrap();
+            for i in 0..100 {
+                let c = texture.value(0.0, 0.0, Point3::new_all(i as f32 * 0.37));
+                assert!(c.x >= 0.0 && c.x <= 1.0, "{} gave {:?}", source, c);
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    environment::luminance,
+    hittable::HitRecord,
+    texture::Texture,
+    vectors::{Point3, Vec3},
+};
+
+/// Optional normal and bump maps of a material, which tilt the shading normal to add detail
+/// that is not in the geometry. The bump map is applied first.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NormalMapping {
+    /// Tangent-space normals encoded as colors in [0, 1], with +x along u and +y towards the
+    /// top of the image, like glTF. Images should be loaded with `srgb: false`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normal_map: Option<Box<dyn Texture>>,
+    /// Multiplies the x and y of the map's normals, like glTF's normal texture scale
+    #[serde(default = "default_scale")]
+    pub normal_scale: f32,
+    /// Height field, from the luminance of the texture
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bump_map: Option<Box<dyn Texture>>,
+    /// Height in world units of a bump map value of 1
+    #[serde(default = "default_scale")]
+    pub bump_scale: f32,
+}
+fn default_scale() -> f32 {
+    1.0
+}
+impl Default for NormalMapping {
+    fn default() -> Self {
+        NormalMapping {
+            normal_map: None,
+            normal_scale: 1.0,
+            bump_map: None,
+            bump_scale: 1.0,
+        }
+    }
+}
+
+impl NormalMapping {
+    /// Perturbs the unit outward `normal` at the hit point by the maps, using the hit's uvs
+    /// and tangents.
+    pub fn apply(&self, rec: &HitRecord, normal: Vec3) -> Vec3 {
+        let mut n = normal;
+        if let Some(bump) = &self.bump_map {
+            n = self.bump(bump.as_ref(), rec, n);
+        }
+        if let Some(map) = &self.normal_map {
+            let c = map.value(rec.u, rec.v, rec.p);
+            let (x, y, z) = (
+                (2.0 * c.x - 1.0) * self.normal_scale,
+                (2.0 * c.y - 1.0) * self.normal_scale,
+                2.0 * c.z - 1.0,
+            );
+            let (t, b) = tangent_frame(n, rec.dpdu, rec.dpdv);
+            let mapped = t * Vec3::new_all(x) + b * Vec3::new_all(y) + n * Vec3::new_all(z);
+            if mapped.length_squared() > 0.0 {
+                n = mapped.normalize();
+            }
+        }
+        n
+    }
+    /// Normal of the surface displaced along `n` by the bump map, from finite differences of
+    /// the height along u and v (ignoring how `n` itself changes).
+    fn bump(&self, bump: &dyn Texture, rec: &HitRecord, n: Vec3) -> Vec3 {
+        const DELTA: f32 = 0.0005;
+        let height = |u: f32, v: f32, p: Point3| luminance(bump.value(u, v, p)) * self.bump_scale;
+        let h = height(rec.u, rec.v, rec.p);
+        let h_u = height(
+            rec.u + DELTA,
+            rec.v,
+            rec.p + rec.dpdu * Vec3::new_all(DELTA),
+        );
+        let h_v = height(
+            rec.u,
+            rec.v + DELTA,
+            rec.p + rec.dpdv * Vec3::new_all(DELTA),
+        );
+        let dpdu = rec.dpdu + n * Vec3::new_all((h_u - h) / DELTA);
+        let dpdv = rec.dpdv + n * Vec3::new_all((h_v - h) / DELTA);
+        let bumped = dpdu.cross(dpdv);
+        if bumped.length_squared() == 0.0 {
+            return n;
+        }
+        let bumped = bumped.normalize();
+        if bumped.dot(n) < 0.0 {
+            -bumped
+        } else {
+            bumped
+        }
+    }
+}
+
+/// Unit tangent along u and bitangent towards decreasing v, both perpendicular to `n`.
+pub fn tangent_frame(n: Vec3, dpdu: Vec3, dpdv: Vec3) -> (Vec3, Vec3) {
+    let t = dpdu - n * Vec3::new_all(n.dot(dpdu));
+    if t.length_squared() < 1e-12 {
+        return n.orthonormal_basis();
+    }
+    let t = t.normalize();
+    let b = n.cross(t);
+    if b.dot(dpdv) > 0.0 {
+        (t, -b)
+    } else {
+        (t, b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::Hittable, hittablelist::HittableList, material::Diffuse, ray::Ray,
+        shapes::sphere::Sphere, texture::SolidColor, vectors::Color,
+    };
+
+    /// Hit on the z = 0 plane facing +z, with u along x and v along -y like an image.
+    fn plane_hit() -> HitRecord {
+        let mut rec = HitRecord::empty();
+        rec.p = Point3::new(0.3, 0.2, 0.0);
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, -1.0, 0.0);
+        rec
+    }
+
+    #[test]
+    fn test_normal_map() {
+        let rec = plane_hit();
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        let map = |color: Color| NormalMapping {
+            normal_map: Some(Box::new(SolidColor { color })),
+            ..NormalMapping::default()
+        };
+        // The flat color leaves the normal alone.
+        let flat = map(Color::new(0.5, 0.5, 1.0)).apply(&rec, n);
+        assert!((flat - n).length() < 1e-6);
+        // +x tilts towards u, +y towards the top of the image.
+        let tilted = map(Color::new(1.0, 0.5, 0.5)).apply(&rec, n);
+        assert!((tilted - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-6);
+        let tilted = map(Color::new(0.5, 1.0, 0.5)).apply(&rec, n);
+        assert!((tilted - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_bump_map() {
+        let rec = plane_hit();
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        let constant = NormalMapping {
+            bump_map: Some(Box::new(SolidColor {
+                color: Color::new_all(0.7),
+            })),
+            ..NormalMapping::default()
+        };
+        assert!((constant.apply(&rec, n) - n).length() < 1e-6);
+
+        // Noise tilts the normal, away from where the surface rises.
+        let noise: Box<dyn Texture> = serde_yaml::from_str("{type: noise, scale: 4}").unwrap();
+        let bumpy = NormalMapping {
+            bump_map: Some(noise.clone()),
+            bump_scale: 0.05,
+            ..NormalMapping::default()
+        };
+        let bumped = bumpy.apply(&rec, n);
+        assert!(bumped.z > 0.0 && bumped.z < 0.9999);
+        assert!((bumped.length() - 1.0).abs() < 1e-5);
+        let slope = noise.value(0.0, 0.0, rec.p + Vec3::new(1e-3, 0.0, 0.0)).x
+            - noise.value(0.0, 0.0, rec.p).x;
+        assert_eq!(bumped.x < 0.0, slope > 0.0);
+    }
+
+    #[test]
+    fn test_normal_map_applied_after_hit() {
+        let mut material = Diffuse::new(Color::new_all(0.5));
+        material.normals.normal_map = Some(Box::new(SolidColor {
+            color: Color::new(1.0, 0.5, 0.5),
+        }));
+        let sphere = Sphere::new(Point3::new_all(0.0), 1.0, Box::new(material));
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        // The object's hit keeps the geometric normal until the closest hit is shaded.
+        let mut rec = sphere.hit(r, 0.001, f32::MAX).unwrap();
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        rec.apply_normal_map();
+        assert!((rec.normal - rec.dpdu.normalize()).length() < 1e-5);
+
+        // The world shades the closest hit, but shadow rays only test for it.
+        let mut world = HittableList::new();
+        world.add(Box::new(sphere));
+        let (_, shaded) = world.hit_object(r, 0.001, f32::MAX).unwrap();
+        assert_eq!(shaded.normal, rec.normal);
+        let mut hit = HitRecord::empty();
+        assert!(world.hit(r, 0.001, f32::MAX, &mut hit));
+        assert_eq!(hit.normal, rec.normal);
+        assert!(world.occluded(r, 0.001, f32::MAX));
+    }
+}
@@ -0,0 +1,138 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+/// Rectangle of pixels rendered as one unit of work, in image coordinates counting from the
+/// bottom left like the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Order in which tiles are handed out to the render threads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileOrder {
+    /// Row by row from the top left
+    Scanline,
+    /// Outwards from the center of the image, where the subject usually is
+    Spiral,
+    /// Along a Hilbert curve, so tiles rendered around the same time are close together
+    Hilbert,
+}
+impl FromStr for TileOrder {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(anyhow!(
+                "unknown tile order {}, expected scanline, spiral or hilbert",
+                s
+            )),
+        }
+    }
+}
+
+/// Splits a `width` x `height` image into tiles of at most `size` x `size` pixels, in the
+/// given order. Tiles on the right and top edges are smaller if the size does not divide
+/// the image.
+pub fn tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = width.div_ceil(size);
+    let rows = height.div_ceil(size);
+    let mut cells = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect::<Vec<_>>();
+    match order {
+        // From the top row down
+        TileOrder::Scanline => cells.sort_by_key(|&(column, row)| (rows - 1 - row, column)),
+        TileOrder::Spiral => {
+            let center = (columns as f32 / 2.0, rows as f32 / 2.0);
+            let ring_and_angle = |&(column, row): &(u32, u32)| {
+                let dx = column as f32 + 0.5 - center.0;
+                let dy = row as f32 + 0.5 - center.1;
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            cells.sort_by(|a, b| ring_and_angle(a).partial_cmp(&ring_and_angle(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            let n = columns.max(rows).next_power_of_two();
+            cells.sort_by_key(|&(column, row)| hilbert_index(n, column, row));
+        }
+    }
+    cells
+        .into_iter()
+        .map(|(column, row)| Tile {
+            x: column * size,
+            y: row * size,
+            width: size.min(width - column * size),
+            height: size.min(height - row * size),
+        })
+        .collect()
+}
+
+/// Distance of (`x`, `y`) along a Hilbert curve filling an `n` x `n` grid, with `n` a power
+/// of two.
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // Rotate the quadrant so the curve continues where the previous one ended.
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiles_cover_image_once() {
+        for order in &[TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let (width, height) = (37, 20);
+            let mut covered = vec![0; (width * height) as usize];
+            for tile in tiles(width, height, 8, *order) {
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        covered[(y * width + x) as usize] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&c| c == 1), "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn test_tile_orders() {
+        let scanline = tiles(32, 32, 8, TileOrder::Scanline);
+        assert_eq!((scanline[0].x, scanline[0].y), (0, 24));
+        assert_eq!((scanline[1].x, scanline[1].y), (8, 24));
+
+        let spiral = tiles(32, 32, 8, TileOrder::Spiral);
+        assert!(spiral[..4]
+            .iter()
+            .all(|t| (8..=16).contains(&t.x) && (8..=16).contains(&t.y)));
+
+        // Consecutive tiles of a Hilbert curve are neighbors.
+        let hilbert = tiles(32, 32, 8, TileOrder::Hilbert);
+        for pair in hilbert.windows(2) {
+            let distance = pair[0].x.abs_diff(pair[1].x) + pair[0].y.abs_diff(pair[1].y);
+            assert_eq!(distance, 8);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::{environment::luminance, vectors::Color};
+
+/// Maps linear radiance to [0, 1] before display encoding.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapper {
+    /// Cuts off everything above 1
+    #[default]
+    Clamp,
+    /// `L / (1 + L)` on luminance
+    Reinhard,
+    /// Reinhard with luminance `white` and above mapped to 1
+    ExtendedReinhard,
+    /// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms
+    Aces,
+    /// Troy Sobotka's AgX, after the polynomial approximation by Benjamin Wrensch
+    Agx,
+}
+impl FromStr for ToneMapper {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneMapper::Clamp),
+            "reinhard" => Ok(ToneMapper::Reinhard),
+            "extended_reinhard" => Ok(ToneMapper::ExtendedReinhard),
+            "aces" => Ok(ToneMapper::Aces),
+            "agx" => Ok(ToneMapper::Agx),
+            _ => Err(anyhow!(
+                "unknown tone mapper {}, expected clamp, reinhard, extended_reinhard, aces or agx",
+                s
+            )),
+        }
+    }
+}
+
+/// How the linear framebuffer is turned into an 8-bit image.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Display {
+    /// Exposure adjustment in stops
+    #[serde(default)]
+    pub exposure: f32,
+    #[serde(default)]
+    pub tonemap: ToneMapper,
+    /// Luminance that maps to 1 with `extended_reinhard`
+    #[serde(default = "default_white")]
+    pub white: f32,
+}
+fn default_white() -> f32 {
+    4.0
+}
+impl Default for Display {
+    fn default() -> Self {
+        Display {
+            exposure: 0.0,
+            tonemap: ToneMapper::default(),
+            white: default_white(),
+        }
+    }
+}
+impl Display {
+    /// Exposes, tone maps and sRGB-encodes a linear color, giving values in [0, 1].
+    pub fn apply(&self, c: Color) -> Color {
+        let exposed = c * Color::new_all(self.exposure.exp2());
+        let mapped = match self.tonemap {
+            ToneMapper::Clamp => exposed,
+            ToneMapper::Reinhard => scale_luminance(exposed, |l| l / (1.0 + l)),
+            ToneMapper::ExtendedReinhard => {
+                let white2 = self.white * self.white;
+                scale_luminance(exposed, |l| l * (1.0 + l / white2) / (1.0 + l))
+            }
+            ToneMapper::Aces => aces(exposed),
+            ToneMapper::Agx => agx(exposed),
+        };
+        Color::new(
+            srgb_oetf(mapped.x),
+            srgb_oetf(mapped.y),
+            srgb_oetf(mapped.z),
+        )
+    }
+}
+
+/// The sRGB transfer function, from linear [0, 1] to encoded [0, 1].
+pub fn srgb_oetf(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.003_130_8 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn scale_luminance(c: Color, f: impl Fn(f32) -> f32) -> Color {
+    let l = luminance(c);
+    if l <= 0.0 {
+        return Color::new_all(0.0);
+    }
+    c * Color::new_all(f(l) / l)
+}
+
+/// Row-major 3x3 matrix times a color.
+fn mat3(m: &[[f32; 3]; 3], c: Color) -> Color {
+    Color::new(
+        m[0][0] * c.x + m[0][1] * c.y + m[0][2] * c.z,
+        m[1][0] * c.x + m[1][1] * c.y + m[1][2] * c.z,
+        m[2][0] * c.x + m[2][1] * c.y + m[2][2] * c.z,
+    )
+}
+fn map_channels(c: Color, f: impl Fn(f32) -> f32) -> Color {
+    Color::new(f(c.x), f(c.y), f(c.z))
+}
+
+fn aces(c: Color) -> Color {
+    // sRGB to the ACES rendering space, with the RRT's saturation adjustment folded in
+    const INPUT: [[f32; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[f32; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let v = mat3(&INPUT, c);
+    let v = map_channels(v, |x| {
+        (x * (x + 0.024_578_6) - 0.000_090_537) / (x * (0.983_729 * x + 0.432_951) + 0.238_081)
+    });
+    mat3(&OUTPUT, v)
+}
+
+fn agx(c: Color) -> Color {
+    const INSET: [[f32; 3]; 3] = [
+        [0.842_479_06, 0.078_433_6, 0.079_223_745],
+        [0.042_328_242, 0.878_468_64, 0.079_166_13],
+        [0.042_375_654, 0.078_433_6, 0.879_143],
+    ];
+    const OUTSET: [[f32; 3]; 3] = [
+        [1.196_879, -0.098_020_88, -0.099_029_74],
+        [-0.052_896_85, 1.151_903_1, -0.098_961_18],
+        [-0.052_971_635, -0.098_043_45, 1.151_073_7],
+    ];
+    const MIN_EV: f32 = -12.473_931;
+    const MAX_EV: f32 = 4.026_069;
+    let v = mat3(&INSET, c);
+    let v = map_channels(v, |x| {
+        let x = (x.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        // Polynomial fit of the AgX base contrast curve
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+    // The curve's output is display encoded with gamma 2.2, so linearize it again.
+    map_channels(mat3(&OUTSET, v), |x| x.max(0.0).powf(2.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_srgb_oetf() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_oetf(0.18) - 0.4614).abs() < 1e-3);
+        assert_eq!(srgb_oetf(7.0), srgb_oetf(1.0));
+    }
+
+    #[test]
+    fn test_tone_mappers_are_monotonic_and_bounded() {
+        for tonemap in &[
+            ToneMapper::Clamp,
+            ToneMapper::Reinhard,
+            ToneMapper::ExtendedReinhard,
+            ToneMapper::Aces,
+            ToneMapper::Agx,
+        ] {
+            let display = Display {
+                tonemap: *tonemap,
+                ..Display::default()
+            };
+            let mut previous = -1.0;
+            for i in 0..200 {
+                let v = display.apply(Color::new_all(i as f32 * 0.1)).x;
+                assert!((0.0..=1.0).contains(&v), "{:?} gave {}", tonemap, v);
+                assert!(v >= previous, "{:?} is not monotonic", tonemap);
+                previous = v;
+            }
+        }
+    }
+
+    #[test]
+    fn test_exposure_doubles_per_stop() {
+        let display = Display {
+            exposure: 1.0,
+            ..Display::default()
+        };
+        assert_eq!(
+            display.apply(Color::new_all(0.25)),
+            Display::default().apply(Color::new_all(0.5))
+        );
+    }
+}
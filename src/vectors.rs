@@ -87,15 +87,80 @@ impl Vec3 {
             return p;
         }
     }
+    pub fn random_in_unit_disk() -> Vec3 {
+        loop {
+            let p = Vec3::new(
+                rand::thread_rng().gen_range(-1.0..1.0),
+                rand::thread_rng().gen_range(-1.0..1.0),
+                0.0,
+            );
+            if p.length_squared() >= 1.0 {
+                continue;
+            }
+            return p;
+        }
+    }
     pub fn random_unit_vector() -> Vec3 {
         Vec3::random_in_unit_sphere().normalize()
     }
+    /// Maps two uniform numbers in [0, 1) to a uniformly distributed direction.
+    pub fn unit_vector_from(u1: f32, u2: f32) -> Vec3 {
+        let z = 1.0 - 2.0 * u1;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+    /// Maps two uniform numbers in [0, 1) to a uniformly distributed point in the unit disk
+    /// at z = 0, using Shirley and Chiu's concentric mapping so strata stay compact.
+    pub fn in_unit_disk_from(u1: f32, u2: f32) -> Vec3 {
+        use std::f32::consts::FRAC_PI_4;
+        let (a, b) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::new_all(0.0);
+        }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, FRAC_PI_4 * (b / a))
+        } else {
+            (b, 2.0 * FRAC_PI_4 - FRAC_PI_4 * (a / b))
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+    }
+    /// Component by index: 0 for x, 1 for y, 2 for z.
+    #[inline(always)]
+    pub fn axis(&self, axis: usize) -> f32 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+    pub fn max_component(&self) -> f32 {
+        self.x.max(self.y).max(self.z)
+    }
     pub fn near_zero(&self) -> bool {
         self.x.abs() < 0.001 && self.y.abs() < 0.001 && self.z.abs() < 0.001
     }
     pub fn reflect(&self, other: Vec3) -> Vec3 {
         *self - other * Vec3::new_all(2.0) * Vec3::new_all(self.dot(other))
     }
+    /// Refracts this unit vector through a surface with normal `n`.
+    pub fn refract(&self, n: Vec3, etai_over_etat: f32) -> Vec3 {
+        let cos_theta = (-*self).dot(n).min(1.0);
+        let r_out_perp = Vec3::new_all(etai_over_etat) * (*self + n * Vec3::new_all(cos_theta));
+        let r_out_parallel = n * Vec3::new_all(-(1.0 - r_out_perp.length_squared()).abs().sqrt());
+        r_out_perp + r_out_parallel
+    }
+    /// Two unit vectors that form an orthonormal basis with this unit vector
+    /// (Duff et al., "Building an Orthonormal Basis, Revisited").
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let sign = 1.0_f32.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (
+            Vec3::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+            Vec3::new(b, sign + self.y * self.y * a, -self.y),
+        )
+    }
 }
 pub type Point3 = Vec3;
 pub type Color = Vec3;
@@ -189,4 +254,17 @@ mod tests {
         assert!(approx_eq!(f32, v3.y, 2.0, ulps = 4));
         assert!(approx_eq!(f32, v3.z, -1.0, ulps = 4));
     }
+    #[test]
+    fn test_vector_refract() {
+        let n = super::Vec3::new(0.0, 1.0, 0.0);
+        let straight = super::Vec3::new(0.0, -1.0, 0.0);
+        let r = straight.refract(n, 1.0 / 1.5);
+        assert!(approx_eq!(f32, r.y, -1.0, ulps = 4));
+
+        // Snell's law: sin(theta_t) = sin(theta_i) * eta
+        let incoming = super::Vec3::new(1.0, -1.0, 0.0).normalize();
+        let r = incoming.refract(n, 1.0 / 1.5);
+        assert!(approx_eq!(f32, r.x, incoming.x / 1.5, epsilon = 1e-6));
+        assert!(approx_eq!(f32, r.length(), 1.0, epsilon = 1e-6));
+    }
 }